@@ -1,46 +1,301 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(name = "rdiff")]
 #[command(author, version, about = "A powerful CLI diff tool with web visualization", long_about = None)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// First file or directory to compare
     #[arg(value_name = "PATH1")]
-    pub path1: String,
+    pub path1: Option<String>,
 
     /// Second file or directory to compare
     #[arg(value_name = "PATH2")]
-    pub path2: String,
+    pub path2: Option<String>,
+
+    /// Remaining arguments of the git external diff protocol
+    #[arg(value_name = "GIT_ARGS", hide = true)]
+    pub git_external_args: Vec<String>,
+
+    /// Interpret the arguments as git's external diff protocol
+    /// (path old-file old-hex old-mode new-file new-hex new-mode); implied by 7 or 9 arguments
+    #[arg(long = "git-external-diff")]
+    pub git_external_diff: bool,
+
+    /// Show an existing unified diff or git patch instead of comparing paths ('-' for standard input).
+    /// A patch piped to rdiff without paths is read the same way
+    #[arg(long = "from-patch", value_name = "FILE", conflicts_with_all = ["path1", "git_external_diff"])]
+    pub from_patch: Option<String>,
+
+    /// Show LABEL instead of the file name (first for PATH1, second for PATH2)
+    #[arg(long = "label", value_name = "LABEL", action = clap::ArgAction::Append)]
+    pub label: Vec<String>,
 
     /// Open diff result in web browser
-    #[arg(short = 'w', long = "web")]
+    #[arg(short = 'O', long = "web", global = true)]
     pub web: bool,
 
+    /// Write a standalone HTML report to FILE instead of printing the diff
+    #[arg(short = 'o', long = "output", visible_alias = "html", value_name = "FILE", global = true)]
+    pub output: Option<String>,
+
     /// Number of context lines in unified diff (default: 3)
-    #[arg(short = 'U', long = "unified", default_value = "3")]
+    #[arg(short = 'U', long = "unified", default_value = "3", global = true)]
     pub unified_lines: usize,
 
-    /// Ignore whitespace changes
-    #[arg(long = "ignore-whitespace")]
+    /// Ignore all white space
+    #[arg(short = 'w', long = "ignore-whitespace", visible_alias = "ignore-all-space", global = true)]
     pub ignore_whitespace: bool,
 
+    /// Ignore changes in the amount of white space
+    #[arg(short = 'b', long = "ignore-space-change", global = true)]
+    pub ignore_space_change: bool,
+
+    /// Ignore changes whose lines are all blank
+    #[arg(short = 'B', long = "ignore-blank-lines", global = true)]
+    pub ignore_blank_lines: bool,
+
     /// Ignore case differences
-    #[arg(short = 'i', long = "ignore-case")]
+    #[arg(short = 'i', long = "ignore-case", global = true)]
     pub ignore_case: bool,
 
+    /// Strip trailing carriage return on input
+    #[arg(long = "strip-trailing-cr", global = true)]
+    pub strip_trailing_cr: bool,
+
+    /// Compare binary files byte by byte and show the differing regions as hex dumps
+    #[arg(long = "binary", global = true)]
+    pub binary: bool,
+
+    /// Read text files in this encoding (e.g. utf-8, latin1, shift_jis, utf-16le) instead of detecting it
+    #[arg(long = "encoding", value_name = "LABEL", global = true)]
+    pub encoding: Option<String>,
+
+    /// Intra-line highlighting of modified lines (none, word, char)
+    #[arg(long = "inline", default_value = "word", value_parser = ["none", "word", "char"], global = true)]
+    pub inline: String,
+
+    /// Diff algorithm (myers, patience, lcs, histogram)
+    #[arg(long = "diff-algorithm", default_value = "myers", value_parser = ["myers", "patience", "lcs", "histogram"], global = true)]
+    pub diff_algorithm: String,
+
+    /// Give up on finding a minimal diff after this many seconds (0: no limit)
+    #[arg(long = "diff-timeout", value_name = "SECS", default_value = "10", global = true)]
+    pub diff_timeout: u64,
+
     /// Show only file names that differ
-    #[arg(short = 'q', long = "brief")]
+    #[arg(short = 'q', long = "brief", global = true)]
     pub brief: bool,
 
+    /// Print nothing; only report differences through the exit status
+    #[arg(long = "quiet", visible_alias = "silent", conflicts_with_all = ["web", "output"], global = true)]
+    pub quiet: bool,
+
+    /// Exit with 1 if there were differences and 0 otherwise (always on; accepted for git compatibility)
+    #[arg(long = "exit-code", global = true)]
+    pub exit_code: bool,
+
     /// Recursively compare directories
-    #[arg(short = 'r', long = "recursive")]
+    #[arg(short = 'r', long = "recursive", global = true)]
     pub recursive: bool,
 
+    /// Do not honor .gitignore, .ignore and .rdiffignore files when comparing directories
+    #[arg(long = "no-ignore", global = true)]
+    pub no_ignore: bool,
+
+    /// Skip files and directories matching GLOB (can be repeated)
+    #[arg(short = 'x', long = "exclude", value_name = "GLOB", global = true)]
+    pub exclude: Vec<String>,
+
+    /// Skip files matching any glob listed in FILE
+    #[arg(short = 'X', long = "exclude-from", value_name = "FILE", global = true)]
+    pub exclude_from: Vec<String>,
+
+    /// Only compare files matching GLOB (can be repeated)
+    #[arg(long = "include", value_name = "GLOB", global = true)]
+    pub include: Vec<String>,
+
+    /// Order of files in directory diffs (path, size, changes, status)
+    #[arg(long = "sort", default_value = "path", value_parser = ["path", "size", "changes", "status"], global = true)]
+    pub sort: String,
+
+    /// Report files that cannot be compared and continue (default)
+    #[arg(long = "keep-going", overrides_with = "fail_fast", global = true)]
+    pub keep_going: bool,
+
+    /// Stop at the first file that cannot be compared
+    #[arg(long = "fail-fast", overrides_with = "keep_going", global = true)]
+    pub fail_fast: bool,
+
+    /// Number of files compared in parallel (default: one per CPU core)
+    #[arg(short = 'j', long = "jobs", value_name = "N", default_value = "0", hide_default_value = true, global = true)]
+    pub jobs: usize,
+
+    /// Detect renamed files in directory diffs, at least PERCENT similar (default 50)
+    #[arg(
+        short = 'M',
+        long = "find-renames",
+        value_name = "PERCENT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "50",
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        global = true
+    )]
+    pub find_renames: Option<u8>,
+
+    /// Detect copied files in directory diffs, at least PERCENT similar (default 50); implies -M
+    #[arg(
+        short = 'C',
+        long = "find-copies",
+        value_name = "PERCENT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "50",
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        global = true
+    )]
+    pub find_copies: Option<u8>,
+
     /// Port for web server (default: random available port)
-    #[arg(long = "port")]
+    #[arg(long = "port", global = true)]
     pub port: Option<u16>,
 
+    /// Output format (text, json, jsonl)
+    #[arg(long = "format", default_value = "text", value_parser = ["text", "json", "jsonl"], global = true)]
+    pub format: String,
+
+    /// Output a normal diff (`3c3`), like GNU diff without options
+    #[arg(long = "normal", conflicts_with_all = ["context", "ed", "rcs", "side_by_side"], global = true)]
+    pub normal: bool,
+
+    /// Output a context diff (`***`/`---`); the number of context lines is set with -U
+    #[arg(short = 'c', long = "context", conflicts_with_all = ["ed", "rcs", "side_by_side"], global = true)]
+    pub context: bool,
+
+    /// Output an ed script that turns the first file into the second
+    #[arg(short = 'e', long = "ed", conflicts_with_all = ["rcs", "side_by_side"], global = true)]
+    pub ed: bool,
+
+    /// Output an RCS format diff
+    #[arg(short = 'n', long = "rcs", conflicts_with = "side_by_side", global = true)]
+    pub rcs: bool,
+
+    /// Output in two columns, old file on the left and new file on the right
+    #[arg(short = 'y', long = "side-by-side", global = true)]
+    pub side_by_side: bool,
+
+    /// Output at most NUM columns in side-by-side mode (default: terminal width, or 130)
+    #[arg(short = 'W', long = "width", value_name = "NUM", requires = "side_by_side", global = true)]
+    pub width: Option<usize>,
+
+    /// Output only the left column of common lines in side-by-side mode
+    #[arg(long = "left-column", requires = "side_by_side", global = true)]
+    pub left_column: bool,
+
+    /// Do not output common lines in side-by-side mode
+    #[arg(long = "suppress-common-lines", requires = "side_by_side", global = true)]
+    pub suppress_common_lines: bool,
+
+    /// Wrap long lines in side-by-side mode instead of truncating them
+    #[arg(long = "wrap", requires = "side_by_side", global = true)]
+    pub wrap: bool,
+
     /// Color output (auto, always, never)
-    #[arg(long = "color", default_value = "auto")]
+    #[arg(long = "color", default_value = "auto", global = true)]
     pub color: String,
 }
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compare git revisions, the index and the working tree (like git diff)
+    ///
+    /// `rdiff git` compares the index with the working tree, `rdiff git REV` a
+    /// revision with the working tree, `rdiff git REV1 REV2` (or `REV1..REV2`)
+    /// two revisions and `rdiff git --cached [REV]` a revision (default HEAD)
+    /// with the index.
+    Git(GitArgs),
+
+    /// Merge two files derived from a common base (like diff3 -m)
+    ///
+    /// Prints the merged file with conflict markers where OURS and THEIRS
+    /// changed the same lines differently. The exit status is 0 for a clean
+    /// merge, 1 when there are conflicts and 2 on trouble. `--format json`
+    /// reports the conflicts as structured data and `--web` shows them side
+    /// by side with the base.
+    Merge(MergeArgs),
+
+    /// Apply a unified diff to the files of a directory (like patch -p1)
+    ///
+    /// Hunks are found even when the file moved them by some lines, and with
+    /// up to FUZZ differing context lines. Hunks that do not apply are
+    /// reported and saved to FILE.rej; the exit status is then 1.
+    Apply(ApplyArgs),
+}
+
+/// Arguments of `rdiff git`
+#[derive(clap::Args, Debug)]
+pub struct GitArgs {
+    /// Compare with the index instead of the working tree
+    #[arg(long = "cached", visible_alias = "staged")]
+    pub cached: bool,
+
+    /// Revisions to compare
+    #[arg(value_name = "REV", num_args = 0..=2)]
+    pub revisions: Vec<String>,
+
+    /// Only compare these paths (git pathspecs, after --)
+    #[arg(value_name = "PATH", last = true)]
+    pub paths: Vec<String>,
+}
+
+/// Arguments of `rdiff merge`
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+    /// Common ancestor of both files
+    #[arg(value_name = "BASE")]
+    pub base: String,
+
+    /// Our version of the file
+    #[arg(value_name = "OURS")]
+    pub ours: String,
+
+    /// Their version of the file
+    #[arg(value_name = "THEIRS")]
+    pub theirs: String,
+
+    /// How to write conflicts (merge, diff3, zdiff3)
+    #[arg(long = "conflict", value_name = "STYLE", default_value = "diff3", value_parser = ["merge", "diff3", "zdiff3"])]
+    pub conflict: String,
+
+    /// Show LABEL instead of a file name; given up to three times for OURS, BASE and THEIRS (like git merge-file -L)
+    #[arg(short = 'L', long = "label", value_name = "LABEL", action = clap::ArgAction::Append)]
+    pub label: Vec<String>,
+}
+
+/// Arguments of `rdiff apply`
+#[derive(clap::Args, Debug)]
+pub struct ApplyArgs {
+    /// Patch to apply (- for standard input)
+    #[arg(value_name = "PATCH")]
+    pub patch: String,
+
+    /// Directory the paths in the patch are relative to
+    #[arg(value_name = "DIR", default_value = ".")]
+    pub dir: String,
+
+    /// Remove NUM leading components from the paths in the patch
+    #[arg(short = 'p', long = "strip", value_name = "NUM", default_value = "1")]
+    pub strip: usize,
+
+    /// Ignore up to NUM differing context lines at each end of a hunk
+    #[arg(short = 'F', long = "fuzz", value_name = "NUM", default_value = "2")]
+    pub fuzz: usize,
+
+    /// Only check whether the patch applies; change no files
+    #[arg(long = "check", visible_alias = "dry-run")]
+    pub check: bool,
+}
@@ -0,0 +1,397 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use super::formatter::{DiffFormatter, patch_path};
+use super::types::*;
+
+/// Context lines GNU patch may ignore at each end of a hunk by default
+pub const DEFAULT_FUZZ: usize = 2;
+
+/// Apply a parsed patch to the files of a directory, like `patch -p1`
+pub struct PatchApplier {
+    fuzz: usize,
+    dry_run: bool,
+}
+
+impl Default for PatchApplier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PatchApplier {
+    pub fn new() -> Self {
+        Self {
+            fuzz: DEFAULT_FUZZ,
+            dry_run: false,
+        }
+    }
+
+    /// Ignore up to this many context lines at the start and end of a hunk
+    /// that does not match otherwise
+    pub fn fuzz(mut self, fuzz: usize) -> Self {
+        self.fuzz = fuzz;
+        self
+    }
+
+    /// Only check whether the patch applies, without writing anything
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Apply every file of the patch to `dir`.
+    ///
+    /// Hunks that apply are written even when others are rejected; rejected
+    /// hunks are saved next to the file in a `.rej` file. Files that cannot be
+    /// patched at all are reported with an error.
+    pub fn apply<P: AsRef<Path>>(&self, patch: &DiffResult, dir: P) -> ApplyReport {
+        let files = patch
+            .files
+            .iter()
+            .map(|file_diff| {
+                self.apply_file(file_diff, dir.as_ref()).unwrap_or_else(|e| AppliedFile {
+                    path: file_diff.path.clone(),
+                    status: file_diff.status,
+                    hunks: vec![],
+                    reject_file: None,
+                    error: Some(format!("{:#}", e)),
+                })
+            })
+            .collect();
+
+        ApplyReport { files }
+    }
+
+    fn apply_file(&self, file_diff: &FileDiff, dir: &Path) -> Result<AppliedFile> {
+        if file_diff.is_binary {
+            bail!("Cannot apply a binary patch");
+        }
+
+        // 新增文件的旧路径和删除文件的新路径是 /dev/null
+        let old_path = target_path(dir, if file_diff.is_new { &file_diff.new_path } else { &file_diff.old_path })?;
+        let new_path = target_path(dir, if file_diff.is_deleted { &file_diff.old_path } else { &file_diff.new_path })?;
+
+        let old_text = if file_diff.is_new {
+            if new_path.exists() {
+                bail!("{} already exists", new_path.display());
+            }
+            String::new()
+        } else {
+            fs::read_to_string(&old_path).with_context(|| format!("Cannot read {}", old_path.display()))?
+        };
+
+        let (new_text, hunks) = apply_hunks(&old_text, file_diff, self.fuzz);
+        let rejected: Vec<&Hunk> = file_diff
+            .hunks
+            .iter()
+            .zip(&hunks)
+            .filter(|(_, outcome)| matches!(outcome, HunkOutcome::Rejected { .. }))
+            .map(|(hunk, _)| hunk)
+            .collect();
+        let target = if file_diff.is_deleted { &old_path } else { &new_path };
+        let reject_file = (!rejected.is_empty()).then(|| format!("{}.rej", target.display()));
+
+        if file_diff.is_deleted && rejected.is_empty() && !new_text.is_empty() {
+            bail!("{} still has content after removing the patch's lines", old_path.display());
+        }
+
+        if !self.dry_run {
+            if let Some(reject_file) = &reject_file {
+                fs::write(reject_file, format_rejects(file_diff, &rejected))
+                    .with_context(|| format!("Cannot write {}", reject_file))?;
+            }
+
+            if file_diff.is_deleted && rejected.is_empty() {
+                fs::remove_file(&old_path).with_context(|| format!("Cannot remove {}", old_path.display()))?;
+            } else {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).with_context(|| format!("Cannot create {}", parent.display()))?;
+                }
+                fs::write(target, &new_text).with_context(|| format!("Cannot write {}", target.display()))?;
+                if file_diff.status == FileStatus::Renamed && old_path != new_path {
+                    fs::remove_file(&old_path).with_context(|| format!("Cannot remove {}", old_path.display()))?;
+                }
+            }
+        }
+
+        Ok(AppliedFile {
+            path: file_diff.path.clone(),
+            status: file_diff.status,
+            hunks,
+            reject_file,
+            error: None,
+        })
+    }
+}
+
+/// Apply the hunks of a file diff to its old text.
+///
+/// Each hunk is looked for where the patch expects it, shifted by the offset
+/// of the previous hunk, then at growing distances from there. When the hunk
+/// does not match anywhere, up to `fuzz` context lines at each end are
+/// ignored. Lines are compared without their line terminators. Added lines
+/// use the line terminator of the file, unless the hunk records `\r`s: its
+/// lines are then written as they are, so line ending changes are applied.
+pub fn apply_hunks(text: &str, file_diff: &FileDiff, fuzz: usize) -> (String, Vec<HunkOutcome>) {
+    let old: Vec<&str> = text.split_inclusive('\n').collect();
+    let eol = if old.first().is_some_and(|line| line.ends_with("\r\n")) { "\r\n" } else { "\n" };
+    let new_last = file_diff.missing_newline.as_ref().and_then(|missing| missing.new);
+
+    let mut output: Vec<String> = Vec::with_capacity(old.len());
+    let mut outcomes = Vec::with_capacity(file_diff.hunks.len());
+    let mut pos = 0;
+    let mut offset: isize = 0;
+
+    for hunk in &file_diff.hunks {
+        // 行数为 0 时 old_start 是插入位置的前一行
+        let start = if hunk.old_count == 0 { hunk.old_start } else { hunk.old_start.saturating_sub(1) };
+
+        let Some((line, fuzz_used, front, back)) = locate_hunk(&old, pos, start as isize + offset, &hunk.lines, fuzz) else {
+            outcomes.push(HunkOutcome::Rejected { line: (start as isize + offset).max(0) as usize + 1 });
+            continue;
+        };
+        let inner = &hunk.lines[front..hunk.lines.len() - back];
+        let keeps_cr = hunk.lines.iter().any(|line| line.content.ends_with('\r'));
+
+        output.extend(old[pos..line].iter().map(|line| line.to_string()));
+        let mut old_index = line;
+        for change in inner {
+            let (takes_old, new_content) = match change.change_type {
+                ChangeType::Context => (true, Some(&change.content)),
+                ChangeType::Delete => (true, None),
+                ChangeType::Add => (false, Some(&change.content)),
+                ChangeType::Modify => (true, Some(&change.content)),
+            };
+            if let Some(content) = new_content {
+                let is_last = new_last.is_some() && change.new_line_num == new_last;
+                let text = match change.change_type {
+                    // 上下文行保留文件中原来的内容和换行符
+                    ChangeType::Context if !is_last => old[old_index].to_string(),
+                    _ if is_last => content.clone(),
+                    _ if keeps_cr => format!("{}\n", content),
+                    _ => format!("{}{}", content, eol),
+                };
+                output.push(text);
+            }
+            if takes_old {
+                old_index += 1;
+            }
+        }
+
+        // 忽略的开头上下文行也属于这个 hunk
+        let hunk_line = line.saturating_sub(front);
+        offset = hunk_line as isize - start as isize;
+        outcomes.push(HunkOutcome::Applied { line: hunk_line + 1, offset, fuzz: fuzz_used });
+        pos = old_index;
+    }
+    output.extend(old[pos..].iter().map(|line| line.to_string()));
+
+    // 补丁在原来没有换行符的最后一行之后添加了内容
+    let last = output.len().saturating_sub(1);
+    for line in &mut output[..last] {
+        if !line.ends_with('\n') {
+            line.push_str(eol);
+        }
+    }
+
+    (output.concat(), outcomes)
+}
+
+/// Find where a hunk applies, at or after `pos` and as close to `expected`
+/// as possible; returns the index of the first matched old line, the fuzz
+/// needed and the number of context lines ignored at the start and the end
+fn locate_hunk(old: &[&str], pos: usize, expected: isize, lines: &[LineChange], fuzz: usize) -> Option<(usize, usize, usize, usize)> {
+    let is_context = |line: &&LineChange| matches!(line.change_type, ChangeType::Context);
+    let leading = lines.iter().take_while(is_context).count();
+    let trailing = lines.iter().rev().take_while(is_context).count();
+
+    let mut tried = None;
+    for fuzz_used in 0..=fuzz {
+        let front = fuzz_used.min(leading);
+        let back = fuzz_used.min(trailing).min(lines.len() - front);
+        if tried == Some((front, back)) {
+            continue;
+        }
+        tried = Some((front, back));
+
+        let inner = &lines[front..lines.len() - back];
+        let old_side: Vec<&str> = inner
+            .iter()
+            .filter_map(|line| match line.change_type {
+                ChangeType::Context => Some(line.old_content.as_deref().unwrap_or(&line.content)),
+                ChangeType::Delete => Some(line.content.as_str()),
+                ChangeType::Modify => line.old_content.as_deref(),
+                ChangeType::Add => None,
+            })
+            .collect();
+        if old_side.len() > old.len() - pos {
+            continue;
+        }
+
+        let last_start = old.len() - old_side.len();
+        let expected = (expected + front as isize).clamp(pos as isize, last_start as isize) as usize;
+        let matches_at = |start: usize| {
+            old[start..start + old_side.len()]
+                .iter()
+                .zip(&old_side)
+                .all(|(line, wanted)| line.trim_end_matches(['\n', '\r']) == wanted.trim_end_matches('\r'))
+        };
+
+        // 从期望的位置开始，交替向后、向前查找
+        let distance = (expected - pos).max(last_start - expected);
+        for d in 0..=distance {
+            let candidates = [
+                Some(expected + d).filter(|&at| at <= last_start),
+                expected.checked_sub(d).filter(|&at| at >= pos && d > 0),
+            ];
+            if let Some(found) = candidates.into_iter().flatten().find(|&at| matches_at(at)) {
+                return Some((found, fuzz_used, front, back));
+            }
+        }
+    }
+
+    None
+}
+
+/// Rejected hunks of a file, in the unified format of the patch
+fn format_rejects(file_diff: &FileDiff, rejected: &[&Hunk]) -> String {
+    let formatter = DiffFormatter::new(false);
+    let mut output = format!(
+        "--- {}\n+++ {}\n",
+        patch_path("a/", &file_diff.old_path),
+        patch_path("b/", &file_diff.new_path)
+    );
+    for hunk in rejected {
+        output.push_str(&formatter.format_hunk(hunk, file_diff.missing_newline.as_ref()));
+    }
+    output
+}
+
+/// Path of a patched file inside `dir`; absolute paths and `..` could write
+/// anywhere and are refused
+fn target_path(dir: &Path, path: &str) -> Result<PathBuf> {
+    let relative = Path::new(path);
+    if relative.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+        bail!("Refusing to patch {} outside the target directory", path);
+    }
+    Ok(dir.join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::directory::DirectoryDiffer;
+    use crate::diff::patch::parse_patch;
+    use tempfile::TempDir;
+
+    fn create_tree(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (path, content) in files {
+            let full_path = dir.path().join(path);
+            fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            fs::write(full_path, content).unwrap();
+        }
+        dir
+    }
+
+    fn diff_trees(dir1: &TempDir, dir2: &TempDir, find_renames: Option<u8>) -> DiffResult {
+        DirectoryDiffer::new(DiffOptions::default())
+            .find_renames(find_renames)
+            .compare_directories(dir1.path().to_str().unwrap(), dir2.path().to_str().unwrap())
+            .unwrap()
+    }
+
+    fn patch_for(text: &str) -> FileDiff {
+        parse_patch(text, 1).unwrap().files.remove(0)
+    }
+
+    #[test]
+    fn test_diff_then_apply_reproduces_new_tree() {
+        let long: String = (1..=30).map(|i| format!("line {}\n", i)).collect();
+        let dir1 = create_tree(&[
+            ("src/lib.rs", long.as_str()),
+            ("no_newline.txt", "a\nb"),
+            ("gone.txt", "bye\n"),
+            ("gone_empty.txt", ""),
+            ("moved/old.txt", "1\n2\n3\n4\n5\n6\n"),
+            ("crlf.txt", "x\r\ny\r\n"),
+        ]);
+        let changed = long.replace("line 2\n", "line two\n").replace("line 29\n", "line 29\nline 29.5\n");
+        let dir2 = create_tree(&[
+            ("src/lib.rs", changed.as_str()),
+            ("no_newline.txt", "a\nc"),
+            ("new/dir/file.txt", "fresh\nfile"),
+            ("new_empty.txt", ""),
+            ("moved/new.txt", "1\n2\n3\n4\n5\n6\n7\n"),
+            ("crlf.txt", "x\r\nz\r\n"),
+        ]);
+
+        let patch = DiffFormatter::new(false).format_unified(&diff_trees(&dir1, &dir2, Some(50)));
+        let parsed = parse_patch(&patch, 1).unwrap();
+        // 两个空文件被识别为重命名
+        assert_eq!(parsed.files.len(), 7);
+
+        let report = PatchApplier::new().apply(&parsed, dir1.path());
+        assert_eq!(report.errors().count(), 0, "{:?}", report);
+        assert_eq!(report.rejected_hunks(), 0);
+
+        assert!(diff_trees(&dir1, &dir2, None).files.is_empty(), "{}", patch);
+        assert_eq!(fs::read(dir1.path().join("crlf.txt")).unwrap(), b"x\r\nz\r\n");
+    }
+
+    #[test]
+    fn test_offset_and_fuzz() {
+        let patch = patch_for("--- a/f\n+++ b/f\n@@ -2,3 +2,3 @@\n b\n-c\n+C\n d\n@@ -8,3 +8,3 @@\n h\n-i\n+I\n j\n");
+
+        // 文件开头多了两行：两个 hunk 都有偏移
+        let (text, outcomes) = apply_hunks("0\n0\na\nb\nc\nd\ne\nf\ng\nh\ni\nj\n", &patch, 0);
+        assert_eq!(text, "0\n0\na\nb\nC\nd\ne\nf\ng\nh\nI\nj\n");
+        assert_eq!(
+            outcomes,
+            vec![
+                HunkOutcome::Applied { line: 4, offset: 2, fuzz: 0 },
+                HunkOutcome::Applied { line: 10, offset: 2, fuzz: 0 },
+            ]
+        );
+
+        // 上下文不同：需要 fuzz
+        let input = "a\nB\nc\nD\ne\nf\ng\nh\ni\nj\n";
+        let (_, outcomes) = apply_hunks(input, &patch, 0);
+        assert_eq!(outcomes[0], HunkOutcome::Rejected { line: 2 });
+        let (text, outcomes) = apply_hunks(input, &patch, 1);
+        assert_eq!(text, "a\nB\nC\nD\ne\nf\ng\nh\nI\nj\n");
+        assert_eq!(outcomes[0], HunkOutcome::Applied { line: 2, offset: 0, fuzz: 1 });
+    }
+
+    #[test]
+    fn test_line_endings() {
+        // 补丁记录了 `\r`：按补丁中的行尾写入
+        let patch = patch_for("--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n-a\r\n-b\r\n+a\n+b\n");
+        assert_eq!(apply_hunks("a\r\nb\r\n", &patch, 0).0, "a\nb\n");
+
+        // 补丁中没有 `\r`：新增的行沿用文件的行尾
+        let patch = patch_for("--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n");
+        assert_eq!(apply_hunks("a\r\nb\r\n", &patch, 0).0, "a\r\nc\r\n");
+    }
+
+    #[test]
+    fn test_rejected_hunks_are_saved() {
+        let dir = create_tree(&[("f.txt", "a\nb\nc\n")]);
+        let patch = parse_patch("--- a/f.txt\n+++ b/f.txt\n@@ -1,3 +1,3 @@\n a\n-x\n+y\n c\n@@ -3 +3,2 @@\n c\n+d\n", 1).unwrap();
+
+        let dry_run = PatchApplier::new().dry_run(true).apply(&patch, dir.path());
+        assert_eq!(dry_run.rejected_hunks(), 1);
+        assert!(!dir.path().join("f.txt.rej").exists());
+
+        let report = PatchApplier::new().apply(&patch, dir.path());
+        assert_eq!(report.files[0].hunks[1], HunkOutcome::Applied { line: 3, offset: 0, fuzz: 0 });
+        assert_eq!(fs::read_to_string(dir.path().join("f.txt")).unwrap(), "a\nb\nc\nd\n");
+        let rejects = fs::read_to_string(dir.path().join("f.txt.rej")).unwrap();
+        assert_eq!(rejects, "--- a/f.txt\n+++ b/f.txt\n@@ -1,3 +1,3 @@\n a\n-x\n+y\n c\n");
+
+        let outside = parse_patch("--- a/../evil\n+++ b/../evil\n@@ -0,0 +1 @@\n+x\n", 1).unwrap();
+        assert!(PatchApplier::new().apply(&outside, dir.path()).files[0].error.is_some());
+    }
+}
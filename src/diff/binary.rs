@@ -0,0 +1,164 @@
+use anyhow::Result;
+use memmap2::Mmap;
+use std::fs::File;
+
+use super::types::{BinaryDiff, BinaryRegion};
+
+/// Bytes per hex dump row; regions always start and end on row boundaries
+pub const BYTES_PER_ROW: usize = 16;
+
+/// Stop collecting regions after this many differing rows (64 KB of dump)
+const MAX_DIFF_ROWS: usize = 4096;
+
+/// Rows are only inspected inside blocks that differ
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Compare two files byte by byte at the same offsets (like `cmp -l`)
+pub fn diff_files(path1: &str, path2: &str) -> Result<BinaryDiff> {
+    let file1 = File::open(path1)?;
+    let file2 = File::open(path2)?;
+    let mmap1 = unsafe { Mmap::map(&file1)? };
+    let mmap2 = unsafe { Mmap::map(&file2)? };
+
+    Ok(diff_bytes(&mmap1, &mmap2))
+}
+
+/// Compare two byte slices at the same offsets.
+///
+/// Differing 16-byte rows are grouped into regions; bytes past the end of the
+/// shorter side count as differing.
+pub fn diff_bytes(old: &[u8], new: &[u8]) -> BinaryDiff {
+    let common_len = old.len().min(new.len());
+    let max_len = old.len().max(new.len());
+
+    let mut first_difference = None;
+    let mut differing_bytes = 0u64;
+    let mut differing_rows: Vec<usize> = Vec::new();
+    let mut truncated = false;
+
+    // 先按块比较，只在不同的块内逐行检查
+    for block_start in (0..common_len).step_by(BLOCK_SIZE) {
+        let block_end = (block_start + BLOCK_SIZE).min(common_len);
+        if old[block_start..block_end] == new[block_start..block_end] {
+            continue;
+        }
+
+        for row_start in (block_start..block_end).step_by(BYTES_PER_ROW) {
+            let row_end = (row_start + BYTES_PER_ROW).min(block_end);
+            let differing = (row_start..row_end).filter(|&i| old[i] != new[i]);
+
+            let mut count = 0;
+            for i in differing {
+                first_difference.get_or_insert(i as u64);
+                count += 1;
+            }
+            if count > 0 {
+                differing_bytes += count;
+                if differing_rows.len() < MAX_DIFF_ROWS {
+                    differing_rows.push(row_start / BYTES_PER_ROW);
+                } else {
+                    truncated = true;
+                }
+            }
+        }
+    }
+
+    // 较短一侧结束之后的字节全部视为不同
+    if max_len > common_len {
+        first_difference.get_or_insert(common_len as u64);
+        differing_bytes += (max_len - common_len) as u64;
+
+        let first_row = common_len / BYTES_PER_ROW;
+        let last_row = (max_len - 1) / BYTES_PER_ROW;
+        for row in first_row..=last_row {
+            if differing_rows.last() == Some(&row) {
+                continue;
+            }
+            if differing_rows.len() < MAX_DIFF_ROWS {
+                differing_rows.push(row);
+            } else {
+                truncated = true;
+                break;
+            }
+        }
+    }
+
+    BinaryDiff {
+        old_size: old.len() as u64,
+        new_size: new.len() as u64,
+        first_difference,
+        differing_bytes,
+        regions: group_rows(&differing_rows, old, new),
+        truncated,
+    }
+}
+
+/// Merge consecutive differing rows into regions
+fn group_rows(rows: &[usize], old: &[u8], new: &[u8]) -> Vec<BinaryRegion> {
+    let mut regions = Vec::new();
+    let mut i = 0;
+
+    while i < rows.len() {
+        let mut j = i + 1;
+        while j < rows.len() && rows[j] == rows[j - 1] + 1 {
+            j += 1;
+        }
+
+        let start = rows[i] * BYTES_PER_ROW;
+        let end = (rows[j - 1] + 1) * BYTES_PER_ROW;
+        regions.push(BinaryRegion {
+            offset: start as u64,
+            old: clip(old, start, end).to_vec(),
+            new: clip(new, start, end).to_vec(),
+        });
+
+        i = j;
+    }
+
+    regions
+}
+
+fn clip(data: &[u8], start: usize, end: usize) -> &[u8] {
+    &data[start.min(data.len())..end.min(data.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_bytes_regions() {
+        let old: Vec<u8> = (0..64).collect();
+        let mut new = old.clone();
+        new[3] = 0xff;
+        new[17] = 0xff;
+        new[50] = 0xff;
+
+        let diff = diff_bytes(&old, &new);
+
+        assert_eq!(diff.first_difference, Some(3));
+        assert_eq!(diff.differing_bytes, 3);
+        // 第 0、1 行相邻合并，第 3 行单独成块
+        let offsets: Vec<u64> = diff.regions.iter().map(|r| r.offset).collect();
+        assert_eq!(offsets, vec![0, 48]);
+        assert_eq!(diff.regions[0].old.len(), 32);
+        assert_eq!(diff.regions[1].new[2], 0xff);
+    }
+
+    #[test]
+    fn test_diff_bytes_size_change() {
+        let old = vec![0u8; 20];
+        let new = vec![0u8; 40];
+
+        let diff = diff_bytes(&old, &new);
+
+        assert_eq!(diff.first_difference, Some(20));
+        assert_eq!(diff.differing_bytes, 20);
+        assert_eq!(diff.regions.len(), 1);
+        assert_eq!(diff.regions[0].offset, 16);
+        assert_eq!(diff.regions[0].old.len(), 4);
+        assert_eq!(diff.regions[0].new.len(), 24);
+
+        assert_eq!(diff_bytes(&old, &old).first_difference, None);
+    }
+}
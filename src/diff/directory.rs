@@ -1,24 +1,165 @@
-use anyhow::Result;
-use std::collections::{HashMap, HashSet};
+use anyhow::{Context, Result};
+use ignore::WalkBuilder;
+use ignore::overrides::{Override, OverrideBuilder};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::Path;
-use walkdir::WalkDir;
 
 use super::file::FileDiffer;
+use super::line_ending::missing_newline;
+use super::rename::{self, Candidate};
 use super::types::*;
+use crate::utils::fs as utils_fs;
+use crate::utils::parallel;
+
+/// Project-specific ignore file, read in addition to `.gitignore` and `.ignore`
+pub const RDIFF_IGNORE_FILENAME: &str = ".rdiffignore";
+
+/// Only show a progress bar when comparing at least this many files
+const PROGRESS_MIN_FILES: usize = 500;
+
+/// Which files a directory comparison looks at
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    /// Honor `.gitignore`, `.ignore` and `.rdiffignore` files and skip `.git/`
+    pub respect_ignore_files: bool,
+    /// Globs of files and directories to skip (gitignore syntax)
+    pub exclude: Vec<String>,
+    /// If not empty, only files matching one of these globs are compared
+    pub include: Vec<String>,
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        Self {
+            respect_ignore_files: true,
+            exclude: Vec::new(),
+            include: Vec::new(),
+        }
+    }
+}
+
+impl PathFilter {
+    /// Read exclude globs from a file, one per line (like GNU diff `--exclude-from`).
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn add_excludes_from(&mut self, path: &str) -> Result<()> {
+        let content = std::fs::read_to_string(path).with_context(|| format!("Cannot read exclude file {}", path))?;
+
+        self.exclude.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        );
+
+        Ok(())
+    }
+
+    /// Build the glob matcher for one directory tree.
+    ///
+    /// Excludes are added last so that they win over includes.
+    fn overrides(&self, root: &Path) -> Result<Override> {
+        let mut builder = OverrideBuilder::new(root);
+        for glob in &self.include {
+            builder.add(glob).with_context(|| format!("Invalid include pattern {}", glob))?;
+        }
+        for glob in &self.exclude {
+            builder
+                .add(&format!("!{}", glob))
+                .with_context(|| format!("Invalid exclude pattern {}", glob))?;
+        }
+        Ok(builder.build()?)
+    }
+}
 
 pub struct DirectoryDiffer {
     file_differ: FileDiffer,
+    filter: PathFilter,
+    jobs: usize,
+    show_progress: bool,
+    sort: SortOrder,
+    fail_fast: bool,
+    find_renames: Option<u8>,
+    find_copies: Option<u8>,
 }
 
 impl DirectoryDiffer {
     pub fn new(options: DiffOptions) -> Self {
+        Self::with_filter(options, PathFilter::default())
+    }
+
+    pub fn with_filter(options: DiffOptions, filter: PathFilter) -> Self {
         Self {
             file_differ: FileDiffer::new(options),
+            filter,
+            jobs: 0,
+            show_progress: false,
+            sort: SortOrder::default(),
+            fail_fast: false,
+            find_renames: None,
+            find_copies: None,
         }
     }
 
+    /// Pair deleted and added files that are at least `threshold` percent
+    /// similar and report them as renames (like `git diff -M`)
+    pub fn find_renames(mut self, threshold: Option<u8>) -> Self {
+        self.find_renames = threshold;
+        self
+    }
+
+    /// Report added files that are at least `threshold` percent similar to a
+    /// deleted or modified file as copies of it (like `git diff -C`).
+    /// Implies rename detection.
+    pub fn find_copies(mut self, threshold: Option<u8>) -> Self {
+        self.find_copies = threshold;
+        self
+    }
+
+    /// Abort on the first file that cannot be compared instead of reporting
+    /// it as an error entry and continuing
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Order of the files in the result
+    pub fn sort(mut self, sort: SortOrder) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Number of files compared in parallel (0: one per CPU core)
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Show a progress bar on stderr for large directories
+    pub fn show_progress(mut self, show_progress: bool) -> Self {
+        self.show_progress = show_progress;
+        self
+    }
+
     /// Compare two directories recursively
     pub fn compare_directories(&self, dir1: &str, dir2: &str) -> Result<DiffResult> {
+        self.compare_directories_with(dir1, dir2, |_| Ok(()))
+    }
+
+    /// Compare two directories recursively, passing every file of the result
+    /// to `on_file` in result order.
+    ///
+    /// In path order without rename or copy detection, each file is passed on
+    /// as soon as it and the files before it have been compared; otherwise the
+    /// order is only known once every file has been compared.
+    pub fn compare_directories_with(
+        &self,
+        dir1: &str,
+        dir2: &str,
+        mut on_file: impl FnMut(&FileDiff) -> Result<()>,
+    ) -> Result<DiffResult> {
         let dir1_path = Path::new(dir1);
         let dir2_path = Path::new(dir2);
 
@@ -31,75 +172,311 @@ impl DirectoryDiffer {
         }
 
         // Collect all files in both directories
-        let files1 = self.collect_files(dir1)?;
-        let files2 = self.collect_files(dir2)?;
+        let mut walk_errors = Vec::new();
+        let files1 = self.collect_files(dir1, &mut walk_errors)?;
+        let files2 = self.collect_files(dir2, &mut walk_errors)?;
 
-        let mut all_paths: HashSet<String> = HashSet::new();
-        all_paths.extend(files1.keys().cloned());
-        all_paths.extend(files2.keys().cloned());
+        // BTreeSet 保证输出顺序与并行调度无关，默认即按路径排序
+        let mut all_paths: BTreeSet<&String> = BTreeSet::new();
+        all_paths.extend(files1.keys());
+        all_paths.extend(files2.keys());
+        let all_paths: Vec<&String> = all_paths.into_iter().collect();
 
-        let mut files = Vec::new();
-        let mut total_additions = 0;
-        let mut total_deletions = 0;
+        let progress = if self.show_progress && all_paths.len() >= PROGRESS_MIN_FILES {
+            let pb = ProgressBar::new(all_paths.len() as u64);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} files {msg}")
+                    .unwrap()
+                    .progress_chars("=>-")
+            );
+            pb.set_message("Comparing...");
+            Some(pb)
+        } else {
+            None
+        };
 
-        for rel_path in all_paths.iter() {
-            let full_path1 = files1.get(rel_path);
-            let full_path2 = files2.get(rel_path);
-
-            let file_diff = match (full_path1, full_path2) {
-                (Some(p1), Some(p2)) => {
-                    // File exists in both directories
-                    self.file_differ.compare_files(p1, p2)?
-                }
-                (Some(p1), None) => {
-                    // File only in dir1 (deleted)
-                    let content = std::fs::read_to_string(p1)?;
-                    self.create_deleted_diff(rel_path, &content)
+        // 并行对比，结果按路径顺序交付
+        let streaming = self.sort == SortOrder::Path && self.find_renames.or(self.find_copies).is_none();
+        let mut streamed = 0;
+        let mut file_diffs = Vec::with_capacity(all_paths.len());
+        parallel::map_in_order(
+            &all_paths,
+            self.jobs,
+            |rel_path| {
+                let (full_path1, full_path2) = (files1.get(*rel_path), files2.get(*rel_path));
+                let file_diff = self.compare_entry_or_error(rel_path, full_path1, full_path2)?;
+                let size = file_size(full_path1).max(file_size(full_path2));
+                if let Some(ref pb) = progress {
+                    pb.inc(1);
                 }
-                (None, Some(p2)) => {
-                    // File only in dir2 (new)
-                    let content = std::fs::read_to_string(p2)?;
-                    self.create_new_diff(rel_path, &content)
+                Ok((file_diff, size))
+            },
+            |(file_diff, size)| {
+                // 与 DiffResult::from_files 一致，只交付有差异或无法对比的文件
+                if streaming && (file_diff.has_changes() || file_diff.has_error()) {
+                    on_file(&file_diff)?;
+                    streamed += 1;
                 }
-                (None, None) => unreachable!(),
-            };
+                file_diffs.push((file_diff, size));
+                Ok(())
+            },
+        )?;
 
-            // Count additions and deletions
-            for hunk in &file_diff.hunks {
-                for line in &hunk.lines {
-                    match line.change_type {
-                        ChangeType::Add => total_additions += 1,
-                        ChangeType::Delete => total_deletions += 1,
-                        _ => {}
-                    }
-                }
-            }
+        if let Some(pb) = progress {
+            pb.finish_and_clear();
+        }
+
+        self.detect_renames(&mut file_diffs, &files1, &files2);
+        file_diffs.extend(walk_errors.into_iter().map(|file_diff| (file_diff, 0)));
+
+        // 稳定排序，键相同的文件保持路径顺序
+        match self.sort {
+            SortOrder::Path => {}
+            SortOrder::Size => file_diffs.sort_by_key(|(_, size)| std::cmp::Reverse(*size)),
+            SortOrder::Changes => file_diffs.sort_by_key(|(file_diff, _)| {
+                let (additions, deletions) = file_diff.count_changes();
+                std::cmp::Reverse(additions + deletions)
+            }),
+            SortOrder::Status => file_diffs.sort_by_key(|(file_diff, _)| status_rank(file_diff)),
+        }
+
+        // 逐个交付时，已交付的文件排在结果最前面，其后只剩遍历目录时的错误
+        let result = DiffResult::from_files(file_diffs.into_iter().map(|(file_diff, _)| file_diff));
+        for file_diff in &result.files[streamed..] {
+            on_file(file_diff)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Replace deleted/added pairs with renames and mark copied files.
+    ///
+    /// A renamed or copied entry takes the place of the added file, so path
+    /// order follows the new name.
+    fn detect_renames(
+        &self,
+        file_diffs: &mut Vec<(FileDiff, u64)>,
+        files1: &HashMap<String, String>,
+        files2: &HashMap<String, String>,
+    ) {
+        let Some(rename_threshold) = self.find_renames.or(self.find_copies) else {
+            return;
+        };
 
-            // Only add if there are actual changes
-            if !file_diff.hunks.is_empty() {
-                files.push(file_diff);
+        let with_status = |status: FileStatus| -> Vec<usize> {
+            (0..file_diffs.len())
+                .filter(|&i| file_diffs[i].0.status == status && !file_diffs[i].0.has_error())
+                .collect()
+        };
+        let deleted_idx = with_status(FileStatus::Deleted);
+        let added_idx = with_status(FileStatus::Added);
+        if added_idx.is_empty() {
+            return;
+        }
+
+        // 读取失败的文件不参与匹配，保持原来的新增/删除条目
+        let load = |indices: &[usize], files: &HashMap<String, String>| -> Vec<(usize, Candidate)> {
+            indices
+                .par_iter()
+                .filter_map(|&i| {
+                    let rel_path = &file_diffs[i].0.path;
+                    Some((i, self.load_candidate(rel_path, files.get(rel_path)?)?))
+                })
+                .collect()
+        };
+        let (deleted_idx, deleted): (Vec<usize>, Vec<Candidate>) = load(&deleted_idx, files1).into_iter().unzip();
+        let (added_idx, added): (Vec<usize>, Vec<Candidate>) = load(&added_idx, files2).into_iter().unzip();
+
+        let mut replaced: Vec<(usize, FileDiff)> = Vec::new();
+        let mut removed = HashSet::new();
+        let mut added_matched = vec![false; added.len()];
+
+        for (i, j, score) in rename::match_renames(&deleted, &added, rename_threshold) {
+            replaced.push((added_idx[j], self.create_rename_diff(&deleted[i], &added[j], FileStatus::Renamed, score)));
+            removed.insert(deleted_idx[i]);
+            added_matched[j] = true;
+        }
+
+        if let Some(copy_threshold) = self.find_copies {
+            // 复制的来源：未被重命名使用的删除文件，以及两边都存在且有修改的文件
+            let source_idx: Vec<usize> = (0..file_diffs.len())
+                .filter(|&i| {
+                    let file_diff = &file_diffs[i].0;
+                    file_diff.status == FileStatus::Modified && file_diff.has_changes() && !file_diff.has_error()
+                })
+                .collect();
+            let mut sources = load(&source_idx, files1);
+            sources.extend(
+                deleted_idx
+                    .iter()
+                    .zip(deleted)
+                    .filter(|(i, _)| !removed.contains(*i))
+                    .map(|(&i, candidate)| (i, candidate)),
+            );
+            sources.sort_by_key(|(i, _)| *i);
+            let sources: Vec<Candidate> = sources.into_iter().map(|(_, candidate)| candidate).collect();
+
+            let (unmatched_idx, unmatched): (Vec<usize>, Vec<Candidate>) = added_idx
+                .iter()
+                .zip(added)
+                .zip(added_matched)
+                .filter(|(_, matched)| !matched)
+                .map(|((&i, candidate), _)| (i, candidate))
+                .unzip();
+
+            for (i, j, score) in rename::match_copies(&sources, &unmatched, copy_threshold) {
+                replaced.push((unmatched_idx[j], self.create_rename_diff(&sources[i], &unmatched[j], FileStatus::Copied, score)));
             }
         }
 
-        Ok(DiffResult {
-            total_files_changed: files.len(),
-            files,
-            total_additions,
-            total_deletions,
+        for (i, file_diff) in replaced {
+            file_diffs[i].0 = file_diff;
+        }
+        *file_diffs = std::mem::take(file_diffs)
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !removed.contains(i))
+            .map(|(_, entry)| entry)
+            .collect();
+    }
+
+    /// Read a file as a rename or copy candidate
+    fn load_candidate(&self, rel_path: &str, full_path: &str) -> Option<Candidate> {
+        let bytes = std::fs::read(full_path).ok()?;
+        let text = if utils_fs::is_binary(&bytes) {
+            None
+        } else {
+            Some(self.file_differ.decode(&bytes).ok()?)
+        };
+
+        Some(Candidate {
+            rel_path: rel_path.to_string(),
+            bytes,
+            text,
         })
     }
 
-    /// Collect all files in directory with relative paths
-    fn collect_files(&self, dir: &str) -> Result<HashMap<String, String>> {
+    /// Entry for a file renamed or copied from `old`, with only the content
+    /// changes as hunks
+    fn create_rename_diff(&self, old: &Candidate, new: &Candidate, status: FileStatus, similarity: u8) -> FileDiff {
+        let file_diff = match (&old.text, &new.text) {
+            (Some(old_text), Some(new_text)) => {
+                self.file_differ.compare_decoded(old_text, new_text, &old.rel_path, &new.rel_path)
+            }
+            // 二进制文件只有完全相同时才会匹配
+            _ => FileDiff {
+                is_binary: false,
+                hunks: vec![],
+                full_content: None,
+                ..self.create_binary_diff(&new.rel_path, true)
+            },
+        };
+
+        FileDiff {
+            path: new.rel_path.clone(),
+            old_path: old.rel_path.clone(),
+            new_path: new.rel_path.clone(),
+            is_new: false,
+            is_deleted: false,
+            status,
+            similarity: Some(similarity),
+            ..file_diff
+        }
+    }
+
+    /// Compare one relative path, turning failures into error entries unless
+    /// `fail_fast` is set
+    fn compare_entry_or_error(
+        &self,
+        rel_path: &str,
+        full_path1: Option<&String>,
+        full_path2: Option<&String>,
+    ) -> Result<FileDiff> {
+        match self.compare_entry(rel_path, full_path1, full_path2) {
+            Ok(file_diff) => Ok(file_diff),
+            Err(e) if self.fail_fast => Err(e.context(format!("Cannot compare {}", rel_path))),
+            Err(e) => Ok(FileDiff::from_error(rel_path, format!("{:#}", e))),
+        }
+    }
+
+    /// Compare one relative path, present in either or both directories
+    fn compare_entry(&self, rel_path: &str, full_path1: Option<&String>, full_path2: Option<&String>) -> Result<FileDiff> {
+        let file_diff = match (full_path1, full_path2) {
+            (Some(p1), Some(p2)) => {
+                // File exists in both directories; paths are relative like
+                // those of added and deleted files
+                FileDiff {
+                    path: rel_path.to_string(),
+                    old_path: rel_path.to_string(),
+                    new_path: rel_path.to_string(),
+                    mtimes: Some(FileTimes::of(p1, p2)),
+                    ..self.file_differ.compare_files(p1, p2)?
+                }
+            }
+            (Some(p1), None) if utils_fs::is_binary_file(p1)? => self.create_binary_diff(rel_path, false),
+            (None, Some(p2)) if utils_fs::is_binary_file(p2)? => self.create_binary_diff(rel_path, true),
+            (Some(p1), None) => {
+                // File only in dir1 (deleted)
+                let content = self.file_differ.read_text(p1)?;
+                self.create_deleted_diff(rel_path, &content.text)
+            }
+            (None, Some(p2)) => {
+                // File only in dir2 (new)
+                let content = self.file_differ.read_text(p2)?;
+                self.create_new_diff(rel_path, &content.text)
+            }
+            (None, None) => unreachable!(),
+        };
+
+        Ok(file_diff)
+    }
+
+    /// Collect all files in directory with relative paths.
+    ///
+    /// Entries that cannot be read are added to `errors` (or abort the walk
+    /// with `fail_fast`).
+    fn collect_files(&self, dir: &str, errors: &mut Vec<FileDiff>) -> Result<HashMap<String, String>> {
         let dir_path = Path::new(dir);
         let mut files = HashMap::new();
 
-        for entry in WalkDir::new(dir)
+        let respect_ignore_files = self.filter.respect_ignore_files;
+        let mut walker = WalkBuilder::new(dir_path);
+        walker
             .follow_links(false)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            if entry.file_type().is_file() {
+            .hidden(false) // 隐藏文件同样参与对比
+            .standard_filters(false)
+            .git_ignore(respect_ignore_files)
+            .git_exclude(respect_ignore_files)
+            .ignore(respect_ignore_files)
+            .parents(respect_ignore_files)
+            .require_git(false) // 非 git 仓库（如解压出的副本）中的 .gitignore 同样生效
+            .overrides(self.filter.overrides(dir_path)?);
+
+        if respect_ignore_files {
+            walker
+                .add_custom_ignore_filename(RDIFF_IGNORE_FILENAME)
+                .filter_entry(|entry| entry.file_name() != ".git");
+        }
+
+        for entry in walker.build() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) if self.fail_fast => return Err(e.into()),
+                Err(e) => {
+                    let rel_path = walk_error_path(&e)
+                        .map(|path| path.strip_prefix(dir_path).unwrap_or(path).to_string_lossy().to_string())
+                        .unwrap_or_else(|| dir.to_string());
+                    errors.push(FileDiff::from_error(&rel_path, e.to_string()));
+                    continue;
+                }
+            };
+
+            // 指向文件的符号链接按内容对比（git difftool --dir-diff 用符号链接指向工作区文件）
+            let is_file = entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file() || (file_type.is_symlink() && entry.path().is_file()));
+            if is_file {
                 let full_path = entry.path();
                 let rel_path = full_path
                     .strip_prefix(dir_path)
@@ -114,8 +491,31 @@ impl DirectoryDiffer {
         Ok(files)
     }
 
+    /// Binary file present in only one directory
+    fn create_binary_diff(&self, rel_path: &str, is_new: bool) -> FileDiff {
+        FileDiff {
+            path: rel_path.to_string(),
+            old_path: if is_new { "/dev/null".to_string() } else { rel_path.to_string() },
+            new_path: if is_new { rel_path.to_string() } else { "/dev/null".to_string() },
+            is_binary: true,
+            is_new,
+            is_deleted: !is_new,
+            hunks: vec![],
+            full_content: None,
+            binary_diff: None,
+            encodings: None,
+            line_endings: None,
+            missing_newline: None,
+            modes: None,
+            mtimes: None,
+            status: if is_new { FileStatus::Added } else { FileStatus::Deleted },
+            similarity: None,
+            error: None,
+        }
+    }
+
     fn create_new_diff(&self, rel_path: &str, content: &str) -> FileDiff {
-        let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+        let lines: Vec<String> = content.split_terminator('\n').map(|s| s.to_string()).collect();
         let mut line_changes = Vec::new();
 
         for (idx, line) in lines.iter().enumerate() {
@@ -124,6 +524,8 @@ impl DirectoryDiffer {
                 old_line_num: None,
                 new_line_num: Some(idx + 1),
                 content: line.clone(),
+                old_content: None,
+                inline: None,
             });
         }
 
@@ -137,30 +539,46 @@ impl DirectoryDiffer {
                     old_line_num: Some(idx + 1),
                     new_line_num: Some(idx + 1),
                     content: line.clone(),
+                    old_content: None,
+                    inline: None,
                 })
                 .collect()
         );
 
         FileDiff {
             path: rel_path.to_string(),
-            old_path: format!("/dev/null"),
+            old_path: "/dev/null".to_string(),
             new_path: rel_path.to_string(),
             is_binary: false,
             is_new: true,
             is_deleted: false,
-            hunks: vec![Hunk {
-                old_start: 0,
-                old_count: 0,
-                new_start: 1,
-                new_count: lines.len(),
-                lines: line_changes,
-            }],
+            // 空文件没有 hunk
+            hunks: if lines.is_empty() {
+                vec![]
+            } else {
+                vec![Hunk {
+                    old_start: 0,
+                    old_count: 0,
+                    new_start: 1,
+                    new_count: lines.len(),
+                    lines: line_changes,
+                }]
+            },
             full_content,
+            binary_diff: None,
+            encodings: None,
+            line_endings: None,
+            missing_newline: missing_newline(b"", content.as_bytes()),
+            modes: None,
+            mtimes: None,
+            status: FileStatus::Added,
+            similarity: None,
+            error: None,
         }
     }
 
     fn create_deleted_diff(&self, rel_path: &str, content: &str) -> FileDiff {
-        let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+        let lines: Vec<String> = content.split_terminator('\n').map(|s| s.to_string()).collect();
         let mut line_changes = Vec::new();
 
         for (idx, line) in lines.iter().enumerate() {
@@ -169,24 +587,315 @@ impl DirectoryDiffer {
                 old_line_num: Some(idx + 1),
                 new_line_num: None,
                 content: line.clone(),
+                old_content: None,
+                inline: None,
             });
         }
 
         FileDiff {
             path: rel_path.to_string(),
             old_path: rel_path.to_string(),
-            new_path: format!("/dev/null"),
+            new_path: "/dev/null".to_string(),
             is_binary: false,
             is_new: false,
             is_deleted: true,
-            hunks: vec![Hunk {
-                old_start: 1,
-                old_count: lines.len(),
-                new_start: 0,
-                new_count: 0,
-                lines: line_changes,
-            }],
+            // 空文件没有 hunk
+            hunks: if lines.is_empty() {
+                vec![]
+            } else {
+                vec![Hunk {
+                    old_start: 1,
+                    old_count: lines.len(),
+                    new_start: 0,
+                    new_count: 0,
+                    lines: line_changes,
+                }]
+            },
             full_content: None, // Deleted files have no new content
+            binary_diff: None,
+            encodings: None,
+            line_endings: None,
+            missing_newline: missing_newline(content.as_bytes(), b""),
+            modes: None,
+            mtimes: None,
+            status: FileStatus::Deleted,
+            similarity: None,
+            error: None,
         }
     }
 }
+
+/// Size in bytes of a file that may be missing on one side
+fn file_size(path: Option<&String>) -> u64 {
+    path.and_then(|path| std::fs::metadata(path).ok())
+        .map_or(0, |metadata| metadata.len())
+}
+
+/// Path of the entry a walk error refers to, if known
+fn walk_error_path(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => walk_error_path(err),
+        ignore::Error::Partial(errs) => errs.iter().find_map(walk_error_path),
+        _ => None,
+    }
+}
+
+/// Group rank for `SortOrder::Status`
+fn status_rank(file_diff: &FileDiff) -> u8 {
+    if file_diff.has_error() {
+        6
+    } else if file_diff.is_binary {
+        5
+    } else if file_diff.is_deleted {
+        4
+    } else if file_diff.is_new {
+        3
+    } else if file_diff.status == FileStatus::Copied {
+        2
+    } else if file_diff.status == FileStatus::Renamed {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// 在临时目录中按 (相对路径, 内容) 创建文件
+    fn create_tree(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (path, content) in files {
+            let full_path = dir.path().join(path);
+            fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            fs::write(full_path, content).unwrap();
+        }
+        dir
+    }
+
+    fn changed_paths(filter: PathFilter, dir1: &TempDir, dir2: &TempDir) -> Vec<String> {
+        let differ = DirectoryDiffer::with_filter(DiffOptions::default(), filter);
+        let result = differ
+            .compare_directories(dir1.path().to_str().unwrap(), dir2.path().to_str().unwrap())
+            .unwrap();
+        let mut paths: Vec<String> = result.files.into_iter().map(|f| f.path).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_ignore_files_are_respected() {
+        let dir1 = create_tree(&[
+            (".gitignore", "target/\n"),
+            (RDIFF_IGNORE_FILENAME, "*.log\n"),
+            ("src/main.rs", "fn main() {}\n"),
+        ]);
+        let dir2 = create_tree(&[
+            (".gitignore", "target/\n"),
+            (RDIFF_IGNORE_FILENAME, "*.log\n"),
+            ("src/main.rs", "fn main() { run() }\n"),
+            ("target/debug/out", "binary\n"),
+            ("build.log", "log\n"),
+            (".git/HEAD", "ref: refs/heads/main\n"),
+        ]);
+
+        assert_eq!(changed_paths(PathFilter::default(), &dir1, &dir2), vec!["src/main.rs"]);
+
+        let no_ignore = PathFilter {
+            respect_ignore_files: false,
+            ..PathFilter::default()
+        };
+        assert_eq!(
+            changed_paths(no_ignore, &dir1, &dir2),
+            vec![".git/HEAD", "build.log", "src/main.rs", "target/debug/out"]
+        );
+    }
+
+    #[test]
+    fn test_include_and_exclude_globs() {
+        let dir1 = create_tree(&[("a.rs", "1\n"), ("b.txt", "1\n"), ("vendor/c.rs", "1\n")]);
+        let dir2 = create_tree(&[("a.rs", "2\n"), ("b.txt", "2\n"), ("vendor/c.rs", "2\n")]);
+
+        let filter = PathFilter {
+            include: vec!["*.rs".to_string()],
+            exclude: vec!["vendor".to_string()],
+            ..PathFilter::default()
+        };
+        assert_eq!(changed_paths(filter, &dir1, &dir2), vec!["a.rs"]);
+    }
+
+    #[test]
+    fn test_parallel_result_is_deterministic() {
+        // 50 个文件分布在 7 个子目录中，i * (i % 3) != i 的 32 个文件内容不同
+        let dir1 = TempDir::new().unwrap();
+        let dir2 = TempDir::new().unwrap();
+        for i in 0..50 {
+            let rel_path = format!("dir{}/file{}.txt", i % 7, i);
+            for (dir, content) in [(&dir1, i), (&dir2, i * (i % 3))] {
+                let full_path = dir.path().join(&rel_path);
+                fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+                fs::write(full_path, format!("a\n{}\n", content)).unwrap();
+            }
+        }
+
+        let run = |jobs| {
+            DirectoryDiffer::new(DiffOptions::default())
+                .jobs(jobs)
+                .compare_directories(dir1.path().to_str().unwrap(), dir2.path().to_str().unwrap())
+                .unwrap()
+        };
+        let sequential = run(1);
+        let parallel = run(8);
+
+        let paths = |result: &DiffResult| result.files.iter().map(|f| f.path.clone()).collect::<Vec<_>>();
+        let mut sorted = paths(&sequential);
+        sorted.sort();
+        assert_eq!(paths(&sequential), sorted);
+        assert_eq!(paths(&parallel), paths(&sequential));
+        assert_eq!(parallel.total_additions, sequential.total_additions);
+        assert_eq!(parallel.total_deletions, sequential.total_deletions);
+        assert_eq!(sequential.total_files_changed, 32);
+    }
+
+    #[test]
+    fn test_sort_orders() {
+        let dir1 = create_tree(&[("a.txt", "1\n"), ("b.txt", "1\n2\n3\n"), ("c.txt", "gone\n")]);
+        let dir2 = create_tree(&[("a.txt", "2\n"), ("b.txt", "4\n5\n6\n7\n"), ("new.txt", "x\n")]);
+
+        let sorted_paths = |sort| {
+            DirectoryDiffer::new(DiffOptions::default())
+                .sort(sort)
+                .compare_directories(dir1.path().to_str().unwrap(), dir2.path().to_str().unwrap())
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|f| f.path)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted_paths(SortOrder::Path), vec!["a.txt", "b.txt", "c.txt", "new.txt"]);
+        assert_eq!(sorted_paths(SortOrder::Size), vec!["b.txt", "c.txt", "a.txt", "new.txt"]);
+        assert_eq!(sorted_paths(SortOrder::Changes), vec!["b.txt", "a.txt", "c.txt", "new.txt"]);
+        assert_eq!(sorted_paths(SortOrder::Status), vec!["a.txt", "b.txt", "new.txt", "c.txt"]);
+    }
+
+    #[test]
+    fn test_binary_files_are_listed() {
+        let dir1 = create_tree(&[("same.bin", "\0a"), ("changed.bin", "\0a"), ("deleted.bin", "\0a")]);
+        let dir2 = create_tree(&[("same.bin", "\0a"), ("changed.bin", "\0b"), ("added.bin", "\0\u{ff}")]);
+
+        let result = DirectoryDiffer::new(DiffOptions::default())
+            .compare_directories(dir1.path().to_str().unwrap(), dir2.path().to_str().unwrap())
+            .unwrap();
+
+        let summary: Vec<_> = result.files.iter().map(|f| (f.path.as_str(), f.is_binary, f.is_new, f.is_deleted)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("added.bin", true, true, false),
+                ("changed.bin", true, false, false),
+                ("deleted.bin", true, false, true),
+            ]
+        );
+        assert_eq!(result.total_files_changed, 3);
+    }
+
+    #[test]
+    fn test_renames_and_copies() {
+        let body = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let dir1 = create_tree(&[("old.txt", body), ("moved.bin", "\0data"), ("base.txt", body), ("gone.txt", "x\n")]);
+        let dir2 = create_tree(&[
+            ("new.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\nten\n"),
+            ("sub/moved.bin", "\0data"),
+            ("base.txt", "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n"),
+            ("copy.txt", body),
+        ]);
+
+        let compare = |differ: DirectoryDiffer| {
+            differ
+                .compare_directories(dir1.path().to_str().unwrap(), dir2.path().to_str().unwrap())
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|f| (f.path, f.old_path, f.status, f.similarity))
+                .collect::<Vec<_>>()
+        };
+
+        let without = compare(DirectoryDiffer::new(DiffOptions::default()));
+        assert!(without.iter().all(|(_, _, status, _)| !matches!(status, FileStatus::Renamed | FileStatus::Copied)));
+
+        // old.txt 与 copy.txt 完全相同，优先于修改过的 new.txt 成为重命名
+        let renames = compare(DirectoryDiffer::new(DiffOptions::default()).find_renames(Some(50)));
+        let summary: Vec<_> = renames.iter().map(|(path, _, status, similarity)| (path.as_str(), *status, *similarity)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("base.txt", FileStatus::Modified, None),
+                ("copy.txt", FileStatus::Renamed, Some(100)),
+                ("gone.txt", FileStatus::Deleted, None),
+                ("new.txt", FileStatus::Added, None),
+                ("sub/moved.bin", FileStatus::Renamed, Some(100)),
+            ]
+        );
+        assert_eq!(renames[1].1, "old.txt");
+        assert_eq!(renames[4].1, "moved.bin");
+
+        // 修改过的 base.txt 可以作为复制来源
+        let copies = compare(DirectoryDiffer::new(DiffOptions::default()).find_copies(Some(50)));
+        assert_eq!(copies[3], ("new.txt".to_string(), "base.txt".to_string(), FileStatus::Copied, Some(90)));
+
+        let strict = compare(DirectoryDiffer::new(DiffOptions::default()).find_copies(Some(95)));
+        assert_eq!(strict[3].2, FileStatus::Added);
+    }
+
+    #[test]
+    fn test_renamed_file_shows_only_content_changes() {
+        let dir1 = create_tree(&[("a/lib.rs", "fn a() {}\nfn b() {}\nfn c() {}\n")]);
+        let dir2 = create_tree(&[("b/lib.rs", "fn a() {}\nfn b() { todo!() }\nfn c() {}\n")]);
+
+        let result = DirectoryDiffer::new(DiffOptions::default())
+            .find_renames(Some(50))
+            .compare_directories(dir1.path().to_str().unwrap(), dir2.path().to_str().unwrap())
+            .unwrap();
+
+        assert_eq!(result.files.len(), 1);
+        let file = &result.files[0];
+        assert_eq!((file.old_path.as_str(), file.new_path.as_str()), ("a/lib.rs", "b/lib.rs"));
+        assert_eq!(file.similarity, Some(66));
+        assert_eq!(file.count_changes(), (1, 1));
+        assert_eq!((result.total_files_changed, result.total_additions, result.total_deletions), (1, 1, 1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_files_are_compared_by_content() {
+        // 与 git difftool --dir-diff 相同：右侧目录中的文件是指向工作区的符号链接
+        let worktree = create_tree(&[("a.txt", "new\n"), ("same.txt", "x\n")]);
+        let dir1 = create_tree(&[("a.txt", "old\n"), ("same.txt", "x\n")]);
+        let dir2 = TempDir::new().unwrap();
+        for name in ["a.txt", "same.txt"] {
+            std::os::unix::fs::symlink(worktree.path().join(name), dir2.path().join(name)).unwrap();
+        }
+
+        assert_eq!(changed_paths(PathFilter::default(), &dir1, &dir2), vec!["a.txt"]);
+    }
+
+    #[test]
+    fn test_unreadable_file_policy() {
+        // 遍历之后被删除的文件无法读取
+        let missing = "/nonexistent/rdiff/file.txt".to_string();
+
+        let differ = DirectoryDiffer::new(DiffOptions::default());
+        let file_diff = differ.compare_entry_or_error("file.txt", Some(&missing), None).unwrap();
+        assert!(file_diff.error.is_some());
+        assert!(file_diff.hunks.is_empty());
+
+        let differ = differ.fail_fast(true);
+        let err = differ.compare_entry_or_error("file.txt", Some(&missing), None).unwrap_err();
+        assert!(err.to_string().contains("file.txt"));
+    }
+}
@@ -1,9 +1,12 @@
-use anyhow::Result;
-use similar::{ChangeTag, TextDiff};
-use std::fs;
+use anyhow::{Context, Result};
+use encoding_rs::UTF_8;
 use std::path::Path;
 
+use super::binary;
+use super::hunk::{build_hunks, diff_lines};
+use super::line_ending::{line_endings, missing_newline};
 use super::types::*;
+use crate::utils::encoding::{self, DecodedText};
 use crate::utils::fs as utils_fs;
 
 pub struct FileDiffer {
@@ -30,34 +33,70 @@ impl FileDiffer {
 
         // Handle new or deleted files
         if !exists1 {
-            let content2 = fs::read_to_string(path2)?;
-            return Ok(self.create_new_file_diff(path2, &content2));
+            let content2 = self.read_text(path2)?;
+            return Ok(self.create_new_file_diff(path2, &content2.text));
         }
 
         if !exists2 {
-            let content1 = fs::read_to_string(path1)?;
-            return Ok(self.create_deleted_file_diff(path1, &content1));
+            let content1 = self.read_text(path1)?;
+            return Ok(self.create_deleted_file_diff(path1, &content1.text));
         }
 
         // Check if files are binary
         if utils_fs::is_binary_file(path1)? || utils_fs::is_binary_file(path2)? {
-            return Ok(FileDiff {
-                path: path1.to_string(),
-                old_path: path1.to_string(),
-                new_path: path2.to_string(),
-                is_binary: true,
-                is_new: false,
-                is_deleted: false,
-                hunks: vec![],
-                full_content: None,
-            });
+            let identical = utils_fs::files_identical(path1, path2)?;
+            let binary_diff = if self.options.binary && !identical {
+                Some(binary::diff_files(path1, path2)?)
+            } else {
+                None
+            };
+
+            return Ok(self.create_binary_file_diff(path1, path2, identical, binary_diff));
         }
 
         // Read file contents
-        let content1 = fs::read_to_string(path1)?;
-        let content2 = fs::read_to_string(path2)?;
+        let content1 = self.read_text(path1)?;
+        let content2 = self.read_text(path2)?;
 
-        Ok(self.compare_text(&content1, &content2, path1, path2))
+        Ok(self.compare_decoded(&content1, &content2, path1, path2))
+    }
+
+    /// Compare two decoded texts, noting their encodings unless both are UTF-8
+    pub fn compare_decoded(&self, old: &DecodedText, new: &DecodedText, old_path: &str, new_path: &str) -> FileDiff {
+        let mut file_diff = self.compare_text(&old.text, &new.text, old_path, new_path);
+        if old.encoding != UTF_8 || new.encoding != UTF_8 {
+            file_diff.encodings = Some(FileEncodings {
+                old: old.encoding.name().to_string(),
+                new: new.encoding.name().to_string(),
+            });
+        }
+
+        file_diff
+    }
+
+    /// Compare two contents already in memory, e.g. read from standard input
+    /// or a pipe
+    pub fn compare_bytes(&self, old: &[u8], new: &[u8], path1: &str, path2: &str) -> Result<FileDiff> {
+        if utils_fs::is_binary(old) || utils_fs::is_binary(new) {
+            let identical = old == new;
+            let binary_diff = (self.options.binary && !identical).then(|| binary::diff_bytes(old, new));
+            return Ok(self.create_binary_file_diff(path1, path2, identical, binary_diff));
+        }
+
+        let content1 = self.decode(old).with_context(|| format!("Cannot decode {}", path1))?;
+        let content2 = self.decode(new).with_context(|| format!("Cannot decode {}", path2))?;
+
+        Ok(self.compare_decoded(&content1, &content2, path1, path2))
+    }
+
+    /// Read a text file in the configured encoding, or a detected one
+    pub fn read_text(&self, path: &str) -> Result<DecodedText> {
+        utils_fs::read_file_with_encoding(path, self.options.encoding)
+    }
+
+    /// Decode file content in the configured encoding, or a detected one
+    pub fn decode(&self, bytes: &[u8]) -> Result<DecodedText> {
+        Ok(encoding::decode(bytes, self.options.encoding)?)
     }
 
     /// Compare two text contents
@@ -68,91 +107,12 @@ impl FileDiffer {
         old_path: &str,
         new_path: &str,
     ) -> FileDiff {
-        let diff = TextDiff::from_lines(old_text, new_text);
-
-        let mut hunks = Vec::new();
-        let mut current_hunk: Option<Hunk> = None;
-        let mut old_line = 1;
-        let mut new_line = 1;
-
-        for change in diff.iter_all_changes() {
-            let line_content = change.value().trim_end_matches(&['\n', '\r'][..]).to_string();
+        let old_lines: Vec<&str> = old_text.split_inclusive('\n').collect();
+        let new_lines: Vec<&str> = new_text.split_inclusive('\n').collect();
+        let lines = diff_lines(&old_lines, &new_lines, 0, 0, &self.options);
 
-            match change.tag() {
-                ChangeTag::Equal => {
-                    let line_change = LineChange {
-                        change_type: ChangeType::Context,
-                        old_line_num: Some(old_line),
-                        new_line_num: Some(new_line),
-                        content: line_content,
-                    };
-
-                    if let Some(ref mut hunk) = current_hunk {
-                        hunk.lines.push(line_change);
-                        hunk.old_count += 1;
-                        hunk.new_count += 1;
-                    }
-
-                    old_line += 1;
-                    new_line += 1;
-                }
-                ChangeTag::Delete => {
-                    let line_change = LineChange {
-                        change_type: ChangeType::Delete,
-                        old_line_num: Some(old_line),
-                        new_line_num: None,
-                        content: line_content,
-                    };
-
-                    if current_hunk.is_none() {
-                        current_hunk = Some(Hunk {
-                            old_start: old_line,
-                            old_count: 0,
-                            new_start: new_line,
-                            new_count: 0,
-                            lines: Vec::new(),
-                        });
-                    }
-
-                    if let Some(ref mut hunk) = current_hunk {
-                        hunk.lines.push(line_change);
-                        hunk.old_count += 1;
-                    }
-
-                    old_line += 1;
-                }
-                ChangeTag::Insert => {
-                    let line_change = LineChange {
-                        change_type: ChangeType::Add,
-                        old_line_num: None,
-                        new_line_num: Some(new_line),
-                        content: line_content,
-                    };
-
-                    if current_hunk.is_none() {
-                        current_hunk = Some(Hunk {
-                            old_start: old_line,
-                            old_count: 0,
-                            new_start: new_line,
-                            new_count: 0,
-                            lines: Vec::new(),
-                        });
-                    }
-
-                    if let Some(ref mut hunk) = current_hunk {
-                        hunk.lines.push(line_change);
-                        hunk.new_count += 1;
-                    }
-
-                    new_line += 1;
-                }
-            }
-        }
-
-        // Add last hunk if exists
-        if let Some(hunk) = current_hunk {
-            hunks.push(hunk);
-        }
+        // Group changes into hunks, keeping only the requested context
+        let hunks = build_hunks(&lines, 0, 0, &self.options);
 
         // Generate full content from new_text for web full view
         let full_content = Some(
@@ -164,6 +124,8 @@ impl FileDiffer {
                     old_line_num: Some(idx + 1),
                     new_line_num: Some(idx + 1),
                     content: line.to_string(),
+                    old_content: None,
+                    inline: None,
                 })
                 .collect()
         );
@@ -177,11 +139,44 @@ impl FileDiffer {
             is_deleted: false,
             hunks,
             full_content,
+            binary_diff: None,
+            encodings: None,
+            line_endings: line_endings(old_text.as_bytes(), new_text.as_bytes(), &self.options),
+            missing_newline: missing_newline(old_text.as_bytes(), new_text.as_bytes()),
+            modes: None,
+            mtimes: None,
+            status: FileStatus::Modified,
+            similarity: None,
+            error: None,
+        }
+    }
+
+    /// Binary files can only be compared byte for byte; identical ones
+    /// produce an empty diff like identical text files do
+    fn create_binary_file_diff(&self, path1: &str, path2: &str, identical: bool, binary_diff: Option<BinaryDiff>) -> FileDiff {
+        FileDiff {
+            path: path1.to_string(),
+            old_path: path1.to_string(),
+            new_path: path2.to_string(),
+            is_binary: !identical,
+            is_new: false,
+            is_deleted: false,
+            hunks: vec![],
+            full_content: None,
+            binary_diff,
+            encodings: None,
+            line_endings: None,
+            missing_newline: None,
+            modes: None,
+            mtimes: None,
+            status: FileStatus::Modified,
+            similarity: None,
+            error: None,
         }
     }
 
     fn create_new_file_diff(&self, path: &str, content: &str) -> FileDiff {
-        let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+        let lines: Vec<String> = content.split_terminator('\n').map(|s| s.to_string()).collect();
         let mut line_changes = Vec::new();
 
         for (idx, line) in lines.iter().enumerate() {
@@ -190,6 +185,8 @@ impl FileDiffer {
                 old_line_num: None,
                 new_line_num: Some(idx + 1),
                 content: line.clone(),
+                old_content: None,
+                inline: None,
             });
         }
 
@@ -203,6 +200,8 @@ impl FileDiffer {
                     old_line_num: Some(idx + 1),
                     new_line_num: Some(idx + 1),
                     content: line.clone(),
+                    old_content: None,
+                    inline: None,
                 })
                 .collect()
         );
@@ -214,19 +213,33 @@ impl FileDiffer {
             is_binary: false,
             is_new: true,
             is_deleted: false,
-            hunks: vec![Hunk {
-                old_start: 0,
-                old_count: 0,
-                new_start: 1,
-                new_count: lines.len(),
-                lines: line_changes,
-            }],
+            // 空文件没有 hunk
+            hunks: if lines.is_empty() {
+                vec![]
+            } else {
+                vec![Hunk {
+                    old_start: 0,
+                    old_count: 0,
+                    new_start: 1,
+                    new_count: lines.len(),
+                    lines: line_changes,
+                }]
+            },
             full_content,
+            binary_diff: None,
+            encodings: None,
+            line_endings: None,
+            missing_newline: missing_newline(b"", content.as_bytes()),
+            modes: None,
+            mtimes: None,
+            status: FileStatus::Added,
+            similarity: None,
+            error: None,
         }
     }
 
     fn create_deleted_file_diff(&self, path: &str, content: &str) -> FileDiff {
-        let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+        let lines: Vec<String> = content.split_terminator('\n').map(|s| s.to_string()).collect();
         let mut line_changes = Vec::new();
 
         for (idx, line) in lines.iter().enumerate() {
@@ -235,6 +248,8 @@ impl FileDiffer {
                 old_line_num: Some(idx + 1),
                 new_line_num: None,
                 content: line.clone(),
+                old_content: None,
+                inline: None,
             });
         }
 
@@ -245,14 +260,28 @@ impl FileDiffer {
             is_binary: false,
             is_new: false,
             is_deleted: true,
-            hunks: vec![Hunk {
-                old_start: 1,
-                old_count: lines.len(),
-                new_start: 0,
-                new_count: 0,
-                lines: line_changes,
-            }],
+            // 空文件没有 hunk
+            hunks: if lines.is_empty() {
+                vec![]
+            } else {
+                vec![Hunk {
+                    old_start: 1,
+                    old_count: lines.len(),
+                    new_start: 0,
+                    new_count: 0,
+                    lines: line_changes,
+                }]
+            },
             full_content: None, // Deleted files have no new content
+            binary_diff: None,
+            encodings: None,
+            line_endings: None,
+            missing_newline: missing_newline(content.as_bytes(), b""),
+            modes: None,
+            mtimes: None,
+            status: FileStatus::Deleted,
+            similarity: None,
+            error: None,
         }
     }
 }
@@ -1,20 +1,64 @@
+use chrono::{DateTime, Local};
 use colored::*;
+use super::binary::BYTES_PER_ROW;
 use super::types::*;
+use crate::utils::input::is_stdin;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Marker printed after a line that has no trailing newline
+pub const NO_NEWLINE_MARKER: &str = "\\ No newline at end of file";
+
+/// Path as written in a patch: relative and prefixed with `a/` or `b/` so that
+/// `git apply` and `patch -p1` find it; `/dev/null` and `-` (standard input)
+/// are kept as they are
+pub fn patch_path(prefix: &str, path: &str) -> String {
+    if path == "/dev/null" || is_stdin(path) {
+        return path.to_string();
+    }
+    let relative = path.trim_start_matches('/');
+    format!("{}{}", prefix, relative.strip_prefix("./").unwrap_or(relative))
+}
 
 pub struct DiffFormatter {
     use_color: bool,
+    file_headers: bool,
+    directories: Option<(String, String)>,
 }
 
 impl DiffFormatter {
     pub fn new(use_color: bool) -> Self {
-        Self { use_color }
+        Self {
+            use_color,
+            file_headers: false,
+            directories: None,
+        }
+    }
+
+    /// Precede each file in the GNU formats with a `diff OPTION OLD NEW`
+    /// line, for inputs that hold several files such as patches
+    pub fn file_headers(mut self, file_headers: bool) -> Self {
+        self.file_headers = file_headers;
+        self
+    }
+
+    /// The inputs were the directories `old_root` and `new_root`: the GNU
+    /// formats print file headers, name each file by joining its path to the
+    /// roots and report files that exist on one side only as
+    /// `Only in DIR: NAME`
+    pub fn directories(mut self, old_root: &str, new_root: &str) -> Self {
+        self.file_headers = true;
+        self.directories = Some((old_root.to_string(), new_root.to_string()));
+        self
     }
 
     /// Format diff result as unified diff
     pub fn format_unified(&self, diff_result: &DiffResult) -> String {
         let mut output = String::new();
 
-        for file_diff in &diff_result.files {
+        // 无法对比的文件在摘要的错误部分列出
+        for file_diff in diff_result.files.iter().filter(|file| !file.has_error()) {
             output.push_str(&self.format_file_diff(file_diff));
             output.push('\n');
         }
@@ -22,32 +66,273 @@ impl DiffFormatter {
         output
     }
 
+    /// Format diff result as a normal diff (`3c3`), the default format of GNU diff
+    pub fn format_normal(&self, diff_result: &DiffResult) -> String {
+        self.format_classic(diff_result, "", |file_diff, _, _| self.format_normal_file(file_diff))
+    }
+
+    /// Format diff result as a context diff (`diff -c`)
+    pub fn format_context(&self, diff_result: &DiffResult) -> String {
+        self.format_classic(diff_result, "-c", |file_diff, old_name, new_name| {
+            self.format_context_file(file_diff, old_name, new_name)
+        })
+    }
+
+    /// Format diff result as an ed script (`diff -e`) that turns the old file
+    /// into the new one
+    pub fn format_ed(&self, diff_result: &DiffResult) -> String {
+        self.format_classic(diff_result, "-e", |file_diff, _, _| format_ed_file(file_diff))
+    }
+
+    /// Format diff result in RCS format (`diff -n`)
+    pub fn format_rcs(&self, diff_result: &DiffResult) -> String {
+        self.format_classic(diff_result, "-n", |file_diff, _, _| format_rcs_file(file_diff))
+    }
+
+    /// Format the files of a diff result in one of the GNU diff formats.
+    ///
+    /// Like GNU diff comparing directories, each file is preceded by a
+    /// `diff [-r] OPTION OLD NEW` line when file headers are enabled, and files or
+    /// directories missing on one side are reported once with `Only in`.
+    /// Binary files are reported in one line and files without changed lines
+    /// print nothing.
+    fn format_classic(
+        &self,
+        diff_result: &DiffResult,
+        option: &str,
+        format_file: impl Fn(&FileDiff, &str, &str) -> String,
+    ) -> String {
+        let mut output = String::new();
+        let mut only_in_lines = HashSet::new();
+
+        for file_diff in diff_result.files.iter().filter(|file| !file.has_error()) {
+            if let Some((old_root, new_root)) = &self.directories
+                && (file_diff.is_new || file_diff.is_deleted)
+            {
+                let line = if file_diff.is_new {
+                    only_in(new_root, old_root, &file_diff.new_path)
+                } else {
+                    only_in(old_root, new_root, &file_diff.old_path)
+                };
+                // 整个目录只存在于一侧时，其中的每个文件都得到同一行
+                if only_in_lines.insert(line.clone()) {
+                    output.push_str(&line);
+                }
+                continue;
+            }
+
+            // 与 GNU diff 一致，目录中的文件以目录路径加相对路径命名
+            let (old_name, new_name) = match &self.directories {
+                Some((old_root, new_root)) => (
+                    Path::new(old_root).join(&file_diff.old_path).display().to_string(),
+                    Path::new(new_root).join(&file_diff.new_path).display().to_string(),
+                ),
+                None => (file_diff.old_path.clone(), file_diff.new_path.clone()),
+            };
+
+            if file_diff.is_binary {
+                output.push_str(&format!("Binary files {} and {} differ\n", old_name, new_name));
+                continue;
+            }
+
+            if self.file_headers {
+                // 与 GNU diff -r 一致：目录对比总是递归的
+                let recursive = if self.directories.is_some() { "-r" } else { "" };
+                let header = ["diff", recursive, option, &old_name, &new_name]
+                    .into_iter()
+                    .filter(|part| !part.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                output.push_str(&self.colorize(&header, "white", true));
+                output.push('\n');
+            }
+            output.push_str(&format_file(file_diff, &old_name, &new_name));
+        }
+
+        output
+    }
+
+    /// Normal format: one `LaR`, `FcT` or `RdL` command per change
+    fn format_normal_file(&self, file_diff: &FileDiff) -> String {
+        let mut output = String::new();
+        let missing = file_diff.missing_newline.as_ref();
+
+        for block in change_blocks(file_diff) {
+            let command = match (block.old.is_empty(), block.new.is_empty()) {
+                (true, _) => format!("{}a{}", block.old_before, line_range(&block.new)),
+                (false, true) => format!("{}d{}", line_range(&block.old), block.new_before),
+                (false, false) => format!("{}c{}", line_range(&block.old), line_range(&block.new)),
+            };
+            output.push_str(&self.colorize(&command, "cyan", false));
+            output.push('\n');
+
+            let old_last = missing.and_then(|m| m.old);
+            for &(line_num, content) in &block.old {
+                output.push_str(&self.format_line("< ", "red", content, None));
+                self.push_no_newline_marker(&mut output, old_last == Some(line_num));
+            }
+            if !block.old.is_empty() && !block.new.is_empty() {
+                output.push_str("---\n");
+            }
+            let new_last = missing.and_then(|m| m.new);
+            for &(line_num, content) in &block.new {
+                output.push_str(&self.format_line("> ", "green", content, None));
+                self.push_no_newline_marker(&mut output, new_last == Some(line_num));
+            }
+        }
+
+        output
+    }
+
+    /// Context format: each hunk lists the old lines, then the new lines;
+    /// a side without changes only has its range line
+    fn format_context_file(&self, file_diff: &FileDiff, old_name: &str, new_name: &str) -> String {
+        let mut output = String::new();
+        if file_diff.hunks.is_empty() {
+            return output;
+        }
+
+        let mtimes = file_diff.mtimes.as_ref();
+        let old_line = format!("*** {}{}", old_name, context_time(mtimes.and_then(|mtimes| mtimes.old)));
+        let new_line = format!("--- {}{}", new_name, context_time(mtimes.and_then(|mtimes| mtimes.new)));
+        output.push_str(&self.colorize(&old_line, "red", true));
+        output.push('\n');
+        output.push_str(&self.colorize(&new_line, "green", true));
+        output.push('\n');
+
+        let missing = file_diff.missing_newline.as_ref();
+        for hunk in &file_diff.hunks {
+            output.push_str("***************\n");
+
+            // 同时有删除和新增的改动用 !，只有删除用 -，只有新增用 +
+            let runs = change_runs(&hunk.lines);
+            let marker = |index: usize, sole: &'static str| {
+                match runs.iter().find(|run| run.lines.contains(&index)) {
+                    Some(run) if run.has_old && run.has_new => "! ",
+                    Some(_) => sole,
+                    None => "  ",
+                }
+            };
+
+            let old_header = format!("*** {} ****", context_range(hunk.old_start, hunk.old_count));
+            output.push_str(&self.colorize(&old_header, "cyan", false));
+            output.push('\n');
+            if runs.iter().any(|run| run.has_old) {
+                let old_last = missing.and_then(|m| m.old);
+                for (index, line) in hunk.lines.iter().enumerate() {
+                    // 修改行和两侧文本不同的上下文行带有旧文本
+                    let content = match line.change_type {
+                        ChangeType::Add => continue,
+                        _ => line.old_content.as_deref().unwrap_or(&line.content),
+                    };
+                    let prefix = marker(index, "- ");
+                    let color = if prefix == "  " { "white" } else { "red" };
+                    output.push_str(&self.format_line(prefix, color, content, None));
+                    self.push_no_newline_marker(&mut output, old_last.is_some() && line.old_line_num == old_last);
+                }
+            }
+
+            let new_header = format!("--- {} ----", context_range(hunk.new_start, hunk.new_count));
+            output.push_str(&self.colorize(&new_header, "cyan", false));
+            output.push('\n');
+            if runs.iter().any(|run| run.has_new) {
+                let new_last = missing.and_then(|m| m.new);
+                for (index, line) in hunk.lines.iter().enumerate() {
+                    if matches!(line.change_type, ChangeType::Delete) {
+                        continue;
+                    }
+                    let prefix = marker(index, "+ ");
+                    let color = if prefix == "  " { "white" } else { "green" };
+                    output.push_str(&self.format_line(prefix, color, &line.content, None));
+                    self.push_no_newline_marker(&mut output, new_last.is_some() && line.new_line_num == new_last);
+                }
+            }
+        }
+
+        output
+    }
+
+    fn push_no_newline_marker(&self, output: &mut String, marked: bool) {
+        if marked {
+            output.push_str(&self.colorize(NO_NEWLINE_MARKER, "white", false));
+            output.push('\n');
+        }
+    }
+
     /// Format a single file diff
     pub fn format_file_diff(&self, file_diff: &FileDiff) -> String {
         let mut output = String::new();
 
-        // File header
-        let header = if file_diff.is_new {
-            format!("diff --git a{} b{}", file_diff.old_path, file_diff.new_path)
-        } else if file_diff.is_deleted {
-            format!("diff --git a{} b{}", file_diff.old_path, file_diff.new_path)
-        } else {
-            format!("diff --git a/{} b/{}", file_diff.old_path, file_diff.new_path)
-        };
+        // File header: a new or deleted file is named after its existing side
+        let old_name = if file_diff.is_new { &file_diff.new_path } else { &file_diff.old_path };
+        let new_name = if file_diff.is_deleted { &file_diff.old_path } else { &file_diff.new_path };
+        let header = format!("diff --git {} {}", patch_path("a/", old_name), patch_path("b/", new_name));
+
+        // 编码和行尾的提示不是 git 的扩展头，放在 diff --git 之前，git apply 会忽略
+        if let Some(encodings) = file_diff.encodings.as_ref().filter(|_| file_diff.encoding_changed()) {
+            let encoding_msg = format!("Encoding changed: {} -> {}", encodings.old, encodings.new);
+            output.push_str(&self.colorize(&encoding_msg, "yellow", false));
+            output.push('\n');
+        }
+
+        if let Some(line_endings) = &file_diff.line_endings {
+            let line_ending_msg = format!("Line endings changed: {} -> {}", line_endings.old, line_endings.new);
+            output.push_str(&self.colorize(&line_ending_msg, "yellow", false));
+            output.push('\n');
+        }
 
         output.push_str(&self.colorize(&header, "white", true));
         output.push('\n');
 
+        // git apply 需要文件模式才能识别新增和删除的文件
+        let modes = file_diff.modes.as_ref();
+        if file_diff.is_new {
+            let mode = modes.and_then(|modes| modes.new.as_deref()).unwrap_or("100644");
+            output.push_str(&format!("new file mode {}\n", mode));
+        } else if file_diff.is_deleted {
+            let mode = modes.and_then(|modes| modes.old.as_deref()).unwrap_or("100644");
+            output.push_str(&format!("deleted file mode {}\n", mode));
+        } else if let Some(modes) = modes.filter(|_| file_diff.mode_changed()) {
+            output.push_str(&format!("old mode {}\n", modes.old.as_deref().unwrap_or_default()));
+            output.push_str(&format!("new mode {}\n", modes.new.as_deref().unwrap_or_default()));
+        }
+
+        // 与 git 相同的重命名/复制扩展头
+        let rename_kind = match file_diff.status {
+            FileStatus::Renamed => Some("rename"),
+            FileStatus::Copied => Some("copy"),
+            _ => None,
+        };
+        if let Some(kind) = rename_kind {
+            if let Some(similarity) = file_diff.similarity {
+                output.push_str(&format!("similarity index {}%\n", similarity));
+            }
+            output.push_str(&format!("{} from {}\n", kind, file_diff.old_path));
+            output.push_str(&format!("{} to {}\n", kind, file_diff.new_path));
+        }
+
         if file_diff.is_binary {
-            let binary_msg = format!("Binary files {} and {} differ", file_diff.old_path, file_diff.new_path);
+            let binary_msg = format!(
+                "Binary files {} and {} differ",
+                patch_path("a/", &file_diff.old_path),
+                patch_path("b/", &file_diff.new_path)
+            );
             output.push_str(&self.colorize(&binary_msg, "white", false));
             output.push('\n');
+            if let Some(binary_diff) = &file_diff.binary_diff {
+                output.push_str(&self.format_binary_diff(binary_diff));
+            }
+            return output;
+        }
+
+        // 内容未变的重命名/复制以及空的新增/删除文件只有扩展头
+        if file_diff.hunks.is_empty() {
             return output;
         }
 
         // --- and +++ lines
-        let old_line = format!("--- {}", file_diff.old_path);
-        let new_line = format!("+++ {}", file_diff.new_path);
+        let old_line = format!("--- {}", patch_path("a/", &file_diff.old_path));
+        let new_line = format!("+++ {}", patch_path("b/", &file_diff.new_path));
         output.push_str(&self.colorize(&old_line, "red", true));
         output.push('\n');
         output.push_str(&self.colorize(&new_line, "green", true));
@@ -55,44 +340,184 @@ impl DiffFormatter {
 
         // Hunks
         for hunk in &file_diff.hunks {
-            output.push_str(&self.format_hunk(hunk));
+            output.push_str(&self.format_hunk(hunk, file_diff.missing_newline.as_ref()));
+        }
+
+        output
+    }
+
+    /// Format a byte-level diff as hex dumps of the differing rows
+    fn format_binary_diff(&self, binary_diff: &BinaryDiff) -> String {
+        let mut output = String::new();
+
+        let delta = binary_diff.new_size as i128 - binary_diff.old_size as i128;
+        output.push_str(&format!(
+            "Size: {} -> {} bytes ({:+})\n",
+            binary_diff.old_size, binary_diff.new_size, delta
+        ));
+        if let Some(offset) = binary_diff.first_difference {
+            output.push_str(&format!(
+                "First difference at offset 0x{:08x} ({}), {} byte(s) differ\n",
+                offset, offset, binary_diff.differing_bytes
+            ));
+        }
+
+        for region in &binary_diff.regions {
+            let header = format!("@@ 0x{:08x} @@", region.offset);
+            output.push_str(&self.colorize(&header, "cyan", true));
+            output.push('\n');
+
+            let rows = region.old.len().max(region.new.len()).div_ceil(BYTES_PER_ROW);
+            for row in 0..rows {
+                let range = row * BYTES_PER_ROW..(row + 1) * BYTES_PER_ROW;
+                let old_row = region.old.get(range.start..range.end.min(region.old.len())).unwrap_or_default();
+                let new_row = region.new.get(range.start..range.end.min(region.new.len())).unwrap_or_default();
+                let offset = region.offset + range.start as u64;
+
+                if !old_row.is_empty() {
+                    output.push_str(&self.format_hex_row("-", "red", offset, old_row, new_row));
+                }
+                if !new_row.is_empty() {
+                    output.push_str(&self.format_hex_row("+", "green", offset, new_row, old_row));
+                }
+            }
+        }
+
+        if binary_diff.truncated {
+            output.push_str("... (too many differences, output truncated)\n");
         }
 
         output
     }
 
+    /// Format one `hexdump -C` style row, emphasizing bytes that differ from `other`
+    fn format_hex_row(&self, prefix: &str, color: &str, offset: u64, bytes: &[u8], other: &[u8]) -> String {
+        let mut hex = String::new();
+        let mut ascii = String::new();
+
+        for i in 0..BYTES_PER_ROW {
+            if i == BYTES_PER_ROW / 2 {
+                hex.push(' ');
+            }
+            let Some(&byte) = bytes.get(i) else {
+                hex.push_str("   ");
+                continue;
+            };
+
+            let byte_hex = format!("{:02x}", byte);
+            let byte_char = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }.to_string();
+            if other.get(i) != Some(&byte) && self.use_color {
+                hex.push_str(&self.emphasize(&byte_hex, color));
+                ascii.push_str(&self.emphasize(&byte_char, color));
+            } else {
+                hex.push_str(&self.colorize(&byte_hex, color, false));
+                ascii.push_str(&self.colorize(&byte_char, color, false));
+            }
+            hex.push(' ');
+        }
+
+        format!(
+            "{}{} {}|{}{}\n",
+            self.colorize(prefix, color, false),
+            self.colorize(&format!("{:08x}:", offset), color, false),
+            hex,
+            ascii,
+            self.colorize("|", color, false)
+        )
+    }
+
     /// Format a hunk
-    fn format_hunk(&self, hunk: &Hunk) -> String {
+    pub fn format_hunk(&self, hunk: &Hunk, missing_newline: Option<&MissingNewline>) -> String {
         let mut output = String::new();
 
-        // Hunk header
+        // Hunk header; a count of 1 is omitted like GNU diff and git do
+        let range = |start: usize, count: usize| {
+            if count == 1 { start.to_string() } else { format!("{},{}", start, count) }
+        };
         let header = format!(
-            "@@ -{},{} +{},{} @@",
-            hunk.old_start, hunk.old_count, hunk.new_start, hunk.new_count
+            "@@ -{} +{} @@",
+            range(hunk.old_start, hunk.old_count),
+            range(hunk.new_start, hunk.new_count)
         );
         output.push_str(&self.colorize(&header, "cyan", true));
         output.push('\n');
 
-        // Lines
+        // `\ No newline at end of file` follows the last line of a side without newline
+        let old_last = missing_newline.and_then(|m| m.old);
+        let new_last = missing_newline.and_then(|m| m.new);
+        let is_old_last = |line: &LineChange| old_last.is_some() && line.old_line_num == old_last;
+        let is_new_last = |line: &LineChange| new_last.is_some() && line.new_line_num == new_last;
+        let with_marker = |mut formatted: String, marker: bool| {
+            if marker {
+                formatted.push_str(&self.colorize(NO_NEWLINE_MARKER, "white", false));
+                formatted.push('\n');
+            }
+            formatted
+        };
+
+        // Lines: within a run of changes, deletions are printed before additions
+        let mut pending_additions: Vec<String> = Vec::new();
         for line in &hunk.lines {
-            output.push_str(&self.format_line(line));
-            output.push('\n');
+            match line.change_type {
+                ChangeType::Context => {
+                    for added in pending_additions.drain(..) {
+                        output.push_str(&added);
+                    }
+                    // 与 GNU diff 一致，上下文行显示旧文件中的内容，补丁才能应用到旧文件
+                    let content = line.old_content.as_deref().unwrap_or(&line.content);
+                    let formatted = self.format_line(" ", "white", content, None);
+                    output.push_str(&with_marker(formatted, is_old_last(line) || is_new_last(line)));
+                }
+                ChangeType::Delete => {
+                    let formatted = self.format_line("-", "red", &line.content, None);
+                    output.push_str(&with_marker(formatted, is_old_last(line)));
+                }
+                ChangeType::Add => {
+                    let formatted = self.format_line("+", "green", &line.content, None);
+                    pending_additions.push(with_marker(formatted, is_new_last(line)));
+                }
+                ChangeType::Modify => {
+                    let old_content = line.old_content.as_deref().unwrap_or_default();
+                    let inline = line.inline.as_ref();
+                    let old_formatted = self.format_line("-", "red", old_content, inline.map(|i| &i.old[..]));
+                    let new_formatted = self.format_line("+", "green", &line.content, inline.map(|i| &i.new[..]));
+                    output.push_str(&with_marker(old_formatted, is_old_last(line)));
+                    pending_additions.push(with_marker(new_formatted, is_new_last(line)));
+                }
+            }
+        }
+        for added in pending_additions {
+            output.push_str(&added);
         }
 
         output
     }
 
-    /// Format a line change
-    fn format_line(&self, line: &LineChange) -> String {
-        let (prefix, color) = match line.change_type {
-            ChangeType::Add => ("+", "green"),
-            ChangeType::Delete => ("-", "red"),
-            ChangeType::Context => (" ", "white"),
-            ChangeType::Modify => ("~", "yellow"),
+    /// Format one diff line, emphasizing intra-line changes when available
+    fn format_line(
+        &self,
+        prefix: &str,
+        color: &str,
+        content: &str,
+        segments: Option<&[InlineSegment]>,
+    ) -> String {
+        let mut line = match segments {
+            Some(segments) if self.use_color => {
+                let mut line = self.colorize(prefix, color, false);
+                for segment in segments {
+                    if segment.emphasized {
+                        line.push_str(&self.emphasize(&segment.text, color));
+                    } else {
+                        line.push_str(&self.colorize(&segment.text, color, false));
+                    }
+                }
+                line
+            }
+            _ => self.colorize(&format!("{}{}", prefix, content), color, false),
         };
 
-        let line_str = format!("{}{}", prefix, line.content);
-        self.colorize(&line_str, color, false)
+        line.push('\n');
+        line
     }
 
     /// Format summary statistics
@@ -104,11 +529,106 @@ impl DiffFormatter {
             diff_result.total_deletions
         );
 
-        self.colorize(&summary, "white", true)
+        let mut output = self.colorize(&summary, "white", true);
+
+        let errors = self.format_errors(diff_result);
+        if !errors.is_empty() {
+            output.push_str("\n\n");
+            output.push_str(errors.trim_end());
+        }
+
+        output
+    }
+
+    /// Format the files that could not be compared, empty if there are none
+    pub fn format_errors(&self, diff_result: &DiffResult) -> String {
+        let mut errors = diff_result.errors().peekable();
+        if errors.peek().is_none() {
+            return String::new();
+        }
+
+        let mut output = String::new();
+        output.push_str(&self.colorize("Errors:", "red", true));
+        output.push('\n');
+        for file in errors {
+            let line = format!("  {}: {}", file.path, file.error.as_deref().unwrap_or_default());
+            output.push_str(&self.colorize(&line, "red", false));
+            output.push('\n');
+        }
+
+        output
+    }
+
+    /// Format the outcome of `rdiff apply`, in the words of GNU patch
+    pub fn format_apply_report(&self, report: &ApplyReport) -> String {
+        let mut output = String::new();
+
+        for file in &report.files {
+            if let Some(error) = &file.error {
+                let line = format!("can't patch {}: {}", file.path, error);
+                output.push_str(&self.colorize(&line, "red", false));
+                output.push('\n');
+                continue;
+            }
+
+            let action = match file.status {
+                FileStatus::Added => "creating",
+                FileStatus::Deleted => "removing",
+                _ => "patching",
+            };
+            output.push_str(&format!("{} file {}\n", action, file.path));
+
+            for (index, hunk) in file.hunks.iter().enumerate() {
+                match *hunk {
+                    HunkOutcome::Applied { offset: 0, fuzz: 0, .. } => {}
+                    HunkOutcome::Applied { line, offset, fuzz } => {
+                        let mut message = format!("Hunk #{} succeeded at {}", index + 1, line);
+                        if fuzz > 0 {
+                            message.push_str(&format!(" with fuzz {}", fuzz));
+                        }
+                        if offset != 0 {
+                            let lines = if offset.abs() == 1 { "line" } else { "lines" };
+                            message.push_str(&format!(" (offset {} {})", offset, lines));
+                        }
+                        output.push_str(&self.colorize(&format!("{}.", message), "yellow", false));
+                        output.push('\n');
+                    }
+                    HunkOutcome::Rejected { line } => {
+                        let message = format!("Hunk #{} FAILED at {}.", index + 1, line);
+                        output.push_str(&self.colorize(&message, "red", false));
+                        output.push('\n');
+                    }
+                }
+            }
+
+            if let Some(reject_file) = &file.reject_file {
+                let message = format!(
+                    "{} out of {} hunk(s) FAILED -- saving rejects to file {}",
+                    file.rejected_hunks(),
+                    file.hunks.len(),
+                    reject_file
+                );
+                output.push_str(&self.colorize(&message, "red", true));
+                output.push('\n');
+            }
+        }
+
+        output
+    }
+
+    /// Highlight an intra-line change with a reversed (background) color
+    pub(crate) fn emphasize(&self, text: &str, color: &str) -> String {
+        let colored = match color {
+            "red" => text.red(),
+            "green" => text.green(),
+            _ => text.normal(),
+        };
+
+        colored.bold().reversed().to_string()
     }
 
     /// Colorize text if color is enabled
-    fn colorize(&self, text: &str, color: &str, bold: bool) -> String {
+    pub(crate) fn colorize(&self, text: &str, color: &str, bold: bool) -> String {
         if !self.use_color {
             return text.to_string();
         }
@@ -129,3 +649,189 @@ impl DiffFormatter {
         }
     }
 }
+
+/// Ed script: the changes from the last to the first, so that the line
+/// numbers of the remaining commands stay valid
+/// Tab and modification time following a file name in a context diff
+/// header, in local time as GNU diff writes them; nothing when unknown
+fn context_time(time: Option<SystemTime>) -> String {
+    time.map(|time| format!("\t{}", DateTime::<Local>::from(time).format("%a %b %e %T %Y")))
+        .unwrap_or_default()
+}
+
+/// GNU diff's `Only in DIR: NAME` line for a file of `root` missing under
+/// `other_root`, naming its topmost directory that is missing as well
+fn only_in(root: &str, other_root: &str, path: &str) -> String {
+    let path = Path::new(path);
+    let missing = path
+        .ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .filter(|ancestor| !Path::new(other_root).join(ancestor).exists())
+        .last()
+        .unwrap_or(path);
+
+    let dir = match missing.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) => Path::new(root).join(parent).display().to_string(),
+        None => root.to_string(),
+    };
+    let name = missing.file_name().unwrap_or(missing.as_os_str()).to_string_lossy();
+    format!("Only in {}: {}\n", dir, name)
+}
+
+fn format_ed_file(file_diff: &FileDiff) -> String {
+    let mut output = String::new();
+
+    for block in change_blocks(file_diff).iter().rev() {
+        match (block.old.is_empty(), block.new.is_empty()) {
+            (true, _) => output.push_str(&format!("{}a\n", block.old_before)),
+            (false, true) => {
+                output.push_str(&format!("{}d\n", line_range(&block.old)));
+                continue;
+            }
+            (false, false) => output.push_str(&format!("{}c\n", line_range(&block.old))),
+        }
+
+        // 单独的 "." 会结束输入模式：与 GNU diff 一样写成 ".." 再用 s/.// 去掉多余的点
+        let mut insert_mode = true;
+        for &(_, content) in &block.new {
+            if !insert_mode {
+                output.push_str("a\n");
+                insert_mode = true;
+            }
+            if content == "." {
+                output.push_str("..\n.\ns/.//\n");
+                insert_mode = false;
+            } else {
+                output.push_str(content);
+                output.push('\n');
+            }
+        }
+        if insert_mode {
+            output.push_str(".\n");
+        }
+    }
+
+    output
+}
+
+/// RCS format: `dN COUNT` and `aN COUNT` commands, with line numbers of the
+/// old file
+fn format_rcs_file(file_diff: &FileDiff) -> String {
+    let mut output = String::new();
+    let new_last = file_diff.missing_newline.as_ref().and_then(|m| m.new);
+
+    for block in change_blocks(file_diff) {
+        let mut insert_after = block.old_before;
+        if let Some(&(first, _)) = block.old.first() {
+            output.push_str(&format!("d{} {}\n", first, block.old.len()));
+            insert_after = block.old.last().map_or(first, |&(last, _)| last);
+        }
+        if !block.new.is_empty() {
+            output.push_str(&format!("a{} {}\n", insert_after, block.new.len()));
+            for &(line_num, content) in &block.new {
+                output.push_str(content);
+                // 与 GNU diff 一致：没有末尾换行的最后一行原样输出
+                if new_last != Some(line_num) {
+                    output.push('\n');
+                }
+            }
+        }
+    }
+
+    output
+}
+
+/// A run of changed lines without context lines in between, with the line
+/// numbers and contents of both sides
+struct ChangeBlock<'a> {
+    old_before: usize, // 改动之前的最后一个旧行号
+    new_before: usize, // 改动之前的最后一个新行号
+    old: Vec<(usize, &'a str)>,
+    new: Vec<(usize, &'a str)>,
+}
+
+/// Split the hunks of a file into change blocks
+fn change_blocks(file_diff: &FileDiff) -> Vec<ChangeBlock<'_>> {
+    let mut blocks = Vec::new();
+
+    for hunk in &file_diff.hunks {
+        // 空范围的起点即为其前一行（与 unified 格式的 -5,0 一致）
+        let mut old_pos = if hunk.old_count == 0 { hunk.old_start } else { hunk.old_start - 1 };
+        let mut new_pos = if hunk.new_count == 0 { hunk.new_start } else { hunk.new_start - 1 };
+        let mut current: Option<ChangeBlock> = None;
+
+        for line in &hunk.lines {
+            if matches!(line.change_type, ChangeType::Context) {
+                blocks.extend(current.take());
+            } else {
+                let block = current.get_or_insert_with(|| ChangeBlock {
+                    old_before: old_pos,
+                    new_before: new_pos,
+                    old: Vec::new(),
+                    new: Vec::new(),
+                });
+                let old_content = match line.change_type {
+                    ChangeType::Modify => line.old_content.as_deref(),
+                    ChangeType::Delete => Some(line.content.as_str()),
+                    _ => None,
+                };
+                if let (Some(line_num), Some(content)) = (line.old_line_num, old_content) {
+                    block.old.push((line_num, content));
+                }
+                if let (Some(line_num), false) = (line.new_line_num, matches!(line.change_type, ChangeType::Delete)) {
+                    block.new.push((line_num, line.content.as_str()));
+                }
+            }
+            old_pos = line.old_line_num.unwrap_or(old_pos);
+            new_pos = line.new_line_num.unwrap_or(new_pos);
+        }
+        blocks.extend(current);
+    }
+
+    blocks
+}
+
+/// Indices of a run of changed lines in a hunk and which sides it changes
+struct ChangeRun {
+    lines: std::ops::Range<usize>,
+    has_old: bool,
+    has_new: bool,
+}
+
+fn change_runs(lines: &[LineChange]) -> Vec<ChangeRun> {
+    let mut runs: Vec<ChangeRun> = Vec::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        let (has_old, has_new) = match line.change_type {
+            ChangeType::Context => continue,
+            ChangeType::Delete => (true, false),
+            ChangeType::Add => (false, true),
+            ChangeType::Modify => (true, true),
+        };
+        match runs.last_mut() {
+            Some(run) if run.lines.end == index => {
+                run.lines.end += 1;
+                run.has_old |= has_old;
+                run.has_new |= has_new;
+            }
+            _ => runs.push(ChangeRun { lines: index..index + 1, has_old, has_new }),
+        }
+    }
+
+    runs
+}
+
+/// `N` or `FIRST,LAST` for the lines of one side of a change block
+fn line_range(lines: &[(usize, &str)]) -> String {
+    match (lines.first(), lines.last()) {
+        (Some(&(first, _)), Some(&(last, _))) if first != last => format!("{},{}", first, last),
+        (Some(&(first, _)), _) => first.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Range of a hunk side in a context diff; an empty range is written as the
+/// line before it, as GNU diff does
+fn context_range(start: usize, count: usize) -> String {
+    if count <= 1 { start.to_string() } else { format!("{},{}", start, start + count - 1) }
+}
@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+
+use super::file::FileDiffer;
+use super::types::*;
+use crate::utils::git::{ChangedPath, ExternalDiffArgs, GitChange, GitRepo, GitSource};
+use crate::utils::input;
+use crate::utils::parallel;
+
+/// Compare revisions, the index and the working tree of a git repository
+pub struct GitDiffer {
+    file_differ: FileDiffer,
+    pathspecs: Vec<String>,
+    fail_fast: bool,
+}
+
+impl GitDiffer {
+    pub fn new(options: DiffOptions) -> Self {
+        Self {
+            file_differ: FileDiffer::new(options),
+            pathspecs: Vec::new(),
+            fail_fast: false,
+        }
+    }
+
+    /// Only compare paths matching these git pathspecs
+    pub fn pathspecs(mut self, pathspecs: Vec<String>) -> Self {
+        self.pathspecs = pathspecs;
+        self
+    }
+
+    /// Abort on the first file that cannot be compared instead of reporting
+    /// it as an error entry and continuing
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Compare two sources of the repository containing `dir`.
+    ///
+    /// Paths in the result are relative to the repository root and sorted.
+    pub fn compare(&self, dir: &str, old: &GitSource, new: &GitSource) -> Result<DiffResult> {
+        self.compare_with(dir, old, new, |_| Ok(()))
+    }
+
+    /// Like `compare`, also passing every file of the result to `on_file` as
+    /// soon as it and the files before it have been compared
+    pub fn compare_with(
+        &self,
+        dir: &str,
+        old: &GitSource,
+        new: &GitSource,
+        mut on_file: impl FnMut(&FileDiff) -> Result<()>,
+    ) -> Result<DiffResult> {
+        let repo = GitRepo::open(dir)?;
+        for source in [old, new] {
+            if let GitSource::Revision(rev) = source {
+                repo.verify_revision(rev)?;
+            }
+        }
+
+        let mut changes = repo.changed_paths(old, new, &self.pathspecs)?;
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut file_diffs = Vec::with_capacity(changes.len());
+        parallel::map_in_order(
+            &changes,
+            0,
+            |change| match self.compare_path(&repo, old, new, change) {
+                Ok(file_diff) => Ok(file_diff),
+                Err(e) if self.fail_fast => Err(e.context(format!("Cannot compare {}", change.path))),
+                Err(e) => Ok(FileDiff::from_error(&change.path, format!("{:#}", e))),
+            },
+            |file_diff| {
+                // 与 DiffResult::from_files 一致，只交付有差异或无法对比的文件
+                if file_diff.has_changes() || file_diff.has_error() {
+                    on_file(&file_diff)?;
+                }
+                file_diffs.push(file_diff);
+                Ok(())
+            },
+        )?;
+
+        Ok(DiffResult::from_files(file_diffs))
+    }
+
+    /// Compare one changed path
+    fn compare_path(&self, repo: &GitRepo, old: &GitSource, new: &GitSource, change: &ChangedPath) -> Result<FileDiff> {
+        let path = &change.path;
+        let old_content = match change.change {
+            GitChange::Added => None,
+            _ => Some(repo.read(old, path)?.with_context(|| format!("{} is missing from {}", path, old))?),
+        };
+        let new_content = match change.change {
+            GitChange::Deleted => None,
+            _ => Some(repo.read(new, path)?.with_context(|| format!("{} is missing from {}", path, new))?),
+        };
+
+        self.compare_contents(old_content.as_deref(), new_content.as_deref(), path, path)
+    }
+
+    /// Compare the temporary files git passes to an external diff program,
+    /// labelled with the path in the repository rather than the file names
+    pub fn compare_external(&self, args: &ExternalDiffArgs) -> Result<FileDiff> {
+        let old_content = match args.old_mode {
+            Some(_) => Some(input::read_input(&args.old_file)?),
+            None => None,
+        };
+        let new_content = match args.new_mode {
+            Some(_) => Some(input::read_input(&args.new_file)?),
+            None => None,
+        };
+
+        let new_path = args.new_path.as_deref().unwrap_or(&args.path);
+        let file_diff = self.compare_contents(old_content.as_deref(), new_content.as_deref(), &args.path, new_path)?;
+
+        if args.new_path.is_some() {
+            return Ok(FileDiff {
+                status: FileStatus::Renamed,
+                similarity: args.similarity,
+                ..file_diff
+            });
+        }
+        Ok(file_diff)
+    }
+
+    /// Compare the contents of a path on both sides; a missing side makes the
+    /// file added or deleted
+    fn compare_contents(&self, old: Option<&[u8]>, new: Option<&[u8]>, old_path: &str, new_path: &str) -> Result<FileDiff> {
+        let file_diff = self.file_differ.compare_bytes(old.unwrap_or_default(), new.unwrap_or_default(), old_path, new_path)?;
+
+        let file_diff = match (old, new) {
+            (None, _) => FileDiff {
+                old_path: "/dev/null".to_string(),
+                is_new: true,
+                status: FileStatus::Added,
+                ..file_diff
+            },
+            (_, None) => FileDiff {
+                new_path: "/dev/null".to_string(),
+                is_deleted: true,
+                full_content: None,
+                status: FileStatus::Deleted,
+                ..file_diff
+            },
+            _ => file_diff,
+        };
+
+        Ok(FileDiff {
+            path: new_path.to_string(),
+            ..file_diff
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com", "-c", "commit.gpgsign=false"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    /// 两次提交：v1 包含 a.txt 和 gone.txt；v2 修改 a.txt、删除 gone.txt、新增 new.txt。
+    /// 之后暂存 a.txt 的第三个版本，并在工作区再修改一次。
+    fn create_repo() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        git(root, &["init", "-q"]);
+
+        fs::write(root.join("a.txt"), "1\n2\n3\n").unwrap();
+        fs::write(root.join("gone.txt"), "bye\n").unwrap();
+        git(root, &["add", "."]);
+        git(root, &["commit", "-q", "-m", "v1"]);
+        git(root, &["tag", "v1"]);
+
+        fs::write(root.join("a.txt"), "1\ntwo\n3\n").unwrap();
+        fs::remove_file(root.join("gone.txt")).unwrap();
+        fs::write(root.join("new.txt"), "hello\n").unwrap();
+        git(root, &["add", "-A"]);
+        git(root, &["commit", "-q", "-m", "v2"]);
+
+        fs::write(root.join("a.txt"), "1\ntwo\nthree\n").unwrap();
+        git(root, &["add", "a.txt"]);
+        fs::write(root.join("a.txt"), "1\ntwo\nthree\nfour\n").unwrap();
+
+        dir
+    }
+
+    fn summary(result: &DiffResult) -> Vec<(String, FileStatus, (usize, usize))> {
+        result.files.iter().map(|f| (f.path.clone(), f.status, f.count_changes())).collect()
+    }
+
+    #[test]
+    fn test_compare_revisions() {
+        let repo = create_repo();
+        let dir = repo.path().to_str().unwrap();
+        let differ = GitDiffer::new(DiffOptions::default());
+
+        let result = differ
+            .compare(dir, &GitSource::Revision("v1".to_string()), &GitSource::Revision("HEAD".to_string()))
+            .unwrap();
+        assert_eq!(
+            summary(&result),
+            vec![
+                ("a.txt".to_string(), FileStatus::Modified, (1, 1)),
+                ("gone.txt".to_string(), FileStatus::Deleted, (0, 1)),
+                ("new.txt".to_string(), FileStatus::Added, (1, 0)),
+            ]
+        );
+        assert_eq!(result.files[2].old_path, "/dev/null");
+
+        let pathspec = GitDiffer::new(DiffOptions::default()).pathspecs(vec!["*.txt".to_string(), ":!a.txt".to_string()]);
+        let result = pathspec
+            .compare(dir, &GitSource::Revision("v1".to_string()), &GitSource::Revision("HEAD".to_string()))
+            .unwrap();
+        assert_eq!(result.files.len(), 2);
+
+        assert!(differ.compare(dir, &GitSource::Revision("no-such-rev".to_string()), &GitSource::WorkingTree).is_err());
+    }
+
+    #[test]
+    fn test_compare_index_and_working_tree() {
+        let repo = create_repo();
+        let dir = repo.path().to_str().unwrap();
+        let differ = GitDiffer::new(DiffOptions::default());
+        let head = GitSource::Revision("HEAD".to_string());
+
+        // 暂存区 -> 工作区：只有 "four" 这一行
+        let unstaged = differ.compare(dir, &GitSource::Index, &GitSource::WorkingTree).unwrap();
+        assert_eq!(summary(&unstaged), vec![("a.txt".to_string(), FileStatus::Modified, (1, 0))]);
+
+        // HEAD -> 暂存区：3 改为 three
+        let staged = differ.compare(dir, &head, &GitSource::Index).unwrap();
+        assert_eq!(summary(&staged), vec![("a.txt".to_string(), FileStatus::Modified, (1, 1))]);
+
+        // HEAD -> 工作区：两者之和
+        let all = differ.compare(dir, &head, &GitSource::WorkingTree).unwrap();
+        assert_eq!(summary(&all), vec![("a.txt".to_string(), FileStatus::Modified, (2, 1))]);
+
+        assert!(differ.compare(dir, &GitSource::WorkingTree, &head).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use similar::algorithms::{Capture, Compact, DiffHook, NoFinishHook, Replace, myers};
+use similar::DiffOp;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Range;
+use std::time::Instant;
+
+/// Elements occurring more often than this are never used as split points
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// Histogram diff of two slices, capturing the ops like
+/// `similar::capture_diff_slices_deadline`.
+pub fn capture_diff_slices_deadline<T>(old: &[T], new: &[T], deadline: Option<Instant>) -> Vec<DiffOp>
+where
+    T: Eq + Hash + Ord,
+{
+    let mut d = Compact::new(Replace::new(Capture::new()), old, new);
+    // Capture 不会返回错误
+    let _ = diff_deadline(&mut d, old, 0..old.len(), new, 0..new.len(), deadline);
+    d.into_inner().into_inner().into_ops()
+}
+
+/// Histogram diff (as in git/JGit).
+///
+/// Each region is split at the matching run whose elements occur least often
+/// in the old side, and both halves are diffed recursively. Regions without a
+/// low-occurrence common element, or reached after the deadline, fall back to
+/// Myers.
+pub fn diff_deadline<D, T>(
+    d: &mut D,
+    old: &[T],
+    old_range: Range<usize>,
+    new: &[T],
+    new_range: Range<usize>,
+    deadline: Option<Instant>,
+) -> Result<(), D::Error>
+where
+    D: DiffHook,
+    T: Eq + Hash + Ord,
+{
+    enum Task {
+        Region(Range<usize>, Range<usize>),
+        Equal(usize, usize, usize),
+    }
+
+    // 用显式栈代替递归，避免大文件上栈溢出；任务按输出顺序的逆序入栈
+    let mut tasks = vec![Task::Region(old_range, new_range)];
+
+    while let Some(task) = tasks.pop() {
+        let (mut old_range, mut new_range) = match task {
+            Task::Equal(old_index, new_index, len) => {
+                d.equal(old_index, new_index, len)?;
+                continue;
+            }
+            Task::Region(old_range, new_range) => (old_range, new_range),
+        };
+
+        // 去掉公共前缀和后缀
+        let prefix = common_prefix_len(old, old_range.clone(), new, new_range.clone());
+        if prefix > 0 {
+            d.equal(old_range.start, new_range.start, prefix)?;
+            old_range.start += prefix;
+            new_range.start += prefix;
+        }
+        let suffix = common_suffix_len(old, old_range.clone(), new, new_range.clone());
+        if suffix > 0 {
+            tasks.push(Task::Equal(old_range.end - suffix, new_range.end - suffix, suffix));
+            old_range.end -= suffix;
+            new_range.end -= suffix;
+        }
+
+        if old_range.is_empty() && new_range.is_empty() {
+            continue;
+        }
+        if old_range.is_empty() {
+            d.insert(old_range.start, new_range.start, new_range.len())?;
+            continue;
+        }
+        if new_range.is_empty() {
+            d.delete(old_range.start, old_range.len(), new_range.start)?;
+            continue;
+        }
+
+        let split = if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            None
+        } else {
+            find_split(old, old_range.clone(), new, new_range.clone())
+        };
+
+        match split {
+            Some((old_index, new_index, len)) => {
+                tasks.push(Task::Region(old_index + len..old_range.end, new_index + len..new_range.end));
+                tasks.push(Task::Equal(old_index, new_index, len));
+                tasks.push(Task::Region(old_range.start..old_index, new_range.start..new_index));
+            }
+            None => {
+                let mut hook = NoFinishHook::new(&mut *d);
+                myers::diff_deadline(&mut hook, old, old_range, new, new_range, deadline)?;
+            }
+        }
+    }
+
+    d.finish()
+}
+
+/// Find the matching run `(old_index, new_index, len)` with the lowest
+/// occurrence count in the old region, preferring longer runs on ties.
+fn find_split<T>(old: &[T], old_range: Range<usize>, new: &[T], new_range: Range<usize>) -> Option<(usize, usize, usize)>
+where
+    T: Eq + Hash,
+{
+    let mut occurrences: HashMap<&T, Vec<usize>> = HashMap::new();
+    for i in old_range.clone() {
+        occurrences.entry(&old[i]).or_default().push(i);
+    }
+
+    // (出现次数, 长度, 旧位置, 新位置)
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut j = new_range.start;
+
+    while j < new_range.end {
+        let mut next_j = j + 1;
+
+        if let Some(positions) = occurrences.get(&new[j])
+            && positions.len() <= MAX_CHAIN_LENGTH
+        {
+            for &i in positions {
+                // 向前、向后扩展匹配区域
+                let (mut start_i, mut start_j) = (i, j);
+                while start_i > old_range.start && start_j > new_range.start && old[start_i - 1] == new[start_j - 1] {
+                    start_i -= 1;
+                    start_j -= 1;
+                }
+                let (mut end_i, mut end_j) = (i + 1, j + 1);
+                while end_i < old_range.end && end_j < new_range.end && old[end_i] == new[end_j] {
+                    end_i += 1;
+                    end_j += 1;
+                }
+
+                let count = (start_i..end_i)
+                    .map(|k| occurrences.get(&old[k]).map_or(usize::MAX, Vec::len))
+                    .min()
+                    .unwrap_or(usize::MAX);
+                let len = end_i - start_i;
+
+                let better = match best {
+                    None => true,
+                    Some((best_count, best_len, _, _)) => count < best_count || (count == best_count && len > best_len),
+                };
+                if better {
+                    best = Some((count, len, start_i, start_j));
+                }
+                next_j = next_j.max(end_j);
+            }
+        }
+
+        j = next_j;
+    }
+
+    best.map(|(_, len, old_index, new_index)| (old_index, new_index, len))
+}
+
+fn common_prefix_len<T: Eq>(old: &[T], old_range: Range<usize>, new: &[T], new_range: Range<usize>) -> usize {
+    old[old_range]
+        .iter()
+        .zip(&new[new_range])
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+fn common_suffix_len<T: Eq>(old: &[T], old_range: Range<usize>, new: &[T], new_range: Range<usize>) -> usize {
+    old[old_range]
+        .iter()
+        .rev()
+        .zip(new[new_range].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use similar::DiffTag;
+
+    /// 按 ops 把旧序列还原成新序列，验证 diff 结果正确
+    fn apply(old: &[&str], new: &[&str], ops: &[DiffOp]) -> Vec<String> {
+        let mut result = Vec::new();
+        for op in ops {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            match tag {
+                DiffTag::Equal => result.extend(old[old_range].iter().map(|s| s.to_string())),
+                DiffTag::Delete => {}
+                _ => result.extend(new[new_range].iter().map(|s| s.to_string())),
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_histogram_reproduces_new() {
+        let old = vec!["fn a() {", "}", "", "fn b() {", "  x", "}", "", "fn c() {", "}"];
+        let new = vec!["fn c() {", "}", "", "fn a() {", "}", "", "fn b() {", "  y", "}"];
+
+        let ops = capture_diff_slices_deadline(&old, &new, None);
+
+        assert_eq!(apply(&old, &new, &ops), new);
+    }
+
+    #[test]
+    fn test_histogram_prefers_unique_lines() {
+        // 唯一的函数头应该对齐，而不是常见的 "}"
+        let old = vec!["}", "fn unique() {", "body", "}"];
+        let new = vec!["fn unique() {", "body", "}", "}"];
+
+        let ops = capture_diff_slices_deadline(&old, &new, None);
+
+        assert!(ops.iter().any(|op| matches!(op.as_tag_tuple(), (DiffTag::Equal, o, n) if o.start == 1 && n.start == 0)));
+        assert_eq!(apply(&old, &new, &ops), new);
+    }
+}
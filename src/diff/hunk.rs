@@ -0,0 +1,404 @@
+use similar::{Algorithm, DiffOp, DiffTag, capture_diff_slices_deadline};
+use std::time::Instant;
+
+use super::histogram;
+
+use super::inline::inline_diff;
+use super::normalize::{is_blank_line, normalize_line};
+use super::types::*;
+
+/// Diff two runs of lines and return every line as a `LineChange`.
+///
+/// Lines may still carry their line terminator; it is stripped for display.
+/// Comparison goes through `normalize_line`, so options such as ignore-case
+/// decide equality while the original text is kept in the output.
+pub fn diff_lines(
+    old_lines: &[&str],
+    new_lines: &[&str],
+    old_offset: usize,
+    new_offset: usize,
+    options: &DiffOptions,
+) -> Vec<LineChange> {
+    let old_keys: Vec<_> = old_lines.iter().map(|l| normalize_line(l, options)).collect();
+    let new_keys: Vec<_> = new_lines.iter().map(|l| normalize_line(l, options)).collect();
+
+    // 只去掉换行符：CRLF 行的 `\r` 留在内容中，补丁写回时才能保持原来的行尾
+    let display = |line: &str| {
+        let content = line.strip_suffix('\n').unwrap_or(line);
+        match options.strip_trailing_cr {
+            true => content.strip_suffix('\r').unwrap_or(content),
+            false => content,
+        }
+        .to_string()
+    };
+
+    let deleted = |old_idx: usize| LineChange {
+        change_type: ChangeType::Delete,
+        old_line_num: Some(old_offset + old_idx + 1),
+        new_line_num: None,
+        content: display(old_lines[old_idx]),
+        old_content: None,
+        inline: None,
+    };
+    let added = |new_idx: usize| LineChange {
+        change_type: ChangeType::Add,
+        old_line_num: None,
+        new_line_num: Some(new_offset + new_idx + 1),
+        content: display(new_lines[new_idx]),
+        old_content: None,
+        inline: None,
+    };
+
+    let mut lines = Vec::with_capacity(old_lines.len().max(new_lines.len()));
+    for op in capture_ops(&old_keys, &new_keys, options) {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+
+        match tag {
+            DiffTag::Equal => {
+                for (old_idx, new_idx) in old_range.zip(new_range) {
+                    // 忽略大小写或空白时相同的行两侧文本可能不同，旧文本另外保存
+                    let old_content = display(old_lines[old_idx]);
+                    let content = display(new_lines[new_idx]);
+                    lines.push(LineChange {
+                        change_type: ChangeType::Context,
+                        old_line_num: Some(old_offset + old_idx + 1),
+                        new_line_num: Some(new_offset + new_idx + 1),
+                        old_content: (old_content != content).then_some(old_content),
+                        content,
+                        inline: None,
+                    });
+                }
+            }
+            DiffTag::Delete => lines.extend(old_range.map(deleted)),
+            DiffTag::Insert => lines.extend(new_range.map(added)),
+            DiffTag::Replace => {
+                // 按顺序配对删除行和新增行，足够相似的配对视为修改行
+                let paired = old_range.len().min(new_range.len());
+                for (old_idx, new_idx) in old_range.clone().zip(new_range.clone()) {
+                    let old_content = display(old_lines[old_idx]);
+                    let new_content = display(new_lines[new_idx]);
+
+                    match inline_diff(&old_content, &new_content, options.inline_mode) {
+                        Some(inline) => lines.push(LineChange {
+                            change_type: ChangeType::Modify,
+                            old_line_num: Some(old_offset + old_idx + 1),
+                            new_line_num: Some(new_offset + new_idx + 1),
+                            content: new_content,
+                            old_content: Some(old_content),
+                            inline: Some(inline),
+                        }),
+                        None => {
+                            lines.push(deleted(old_idx));
+                            lines.push(added(new_idx));
+                        }
+                    }
+                }
+                lines.extend(old_range.skip(paired).map(deleted));
+                lines.extend(new_range.skip(paired).map(added));
+            }
+        }
+    }
+
+    lines
+}
+
+/// Run the configured diff algorithm, honoring the optional timeout
+fn capture_ops<T: Eq + std::hash::Hash + Ord>(old: &[T], new: &[T], options: &DiffOptions) -> Vec<DiffOp> {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+
+    match options.algorithm {
+        DiffAlgorithm::Myers => capture_diff_slices_deadline(Algorithm::Myers, old, new, deadline),
+        DiffAlgorithm::Patience => capture_diff_slices_deadline(Algorithm::Patience, old, new, deadline),
+        DiffAlgorithm::Lcs => capture_diff_slices_deadline(Algorithm::Lcs, old, new, deadline),
+        DiffAlgorithm::Histogram => histogram::capture_diff_slices_deadline(old, new, deadline),
+    }
+}
+
+/// Group a contiguous run of line changes into unified-diff hunks.
+///
+/// `lines` must cover a contiguous region of both files, starting right after
+/// `old_offset` old lines and `new_offset` new lines. Each hunk keeps at most
+/// `context_lines` unchanged lines before and after its changes, and two
+/// changes are kept in the same hunk when no more than `2 * context_lines`
+/// unchanged lines separate them (same grouping rules as GNU diff). With
+/// `ignore_blank_lines`, changes made only of blank lines do not open a hunk.
+pub fn build_hunks(
+    lines: &[LineChange],
+    old_offset: usize,
+    new_offset: usize,
+    options: &DiffOptions,
+) -> Vec<Hunk> {
+    let ranges = change_ranges(lines, options);
+    hunks_from_ranges(lines, old_offset, new_offset, ranges)
+}
+
+/// Like `build_hunks`, but also keeps the first and last `context_lines`
+/// lines of the run.
+///
+/// Used for the segments of chunked diffs: a change near a segment boundary
+/// needs context from the neighbouring segment, which `ChunkedDiffer::merge_hunks`
+/// can only provide if both sides of the boundary were kept.
+pub fn build_segment_hunks(
+    lines: &[LineChange],
+    old_offset: usize,
+    new_offset: usize,
+    options: &DiffOptions,
+) -> Vec<Hunk> {
+    let context = options.context_lines.min(lines.len());
+    let mut ranges = change_ranges(lines, options);
+
+    if context > 0 {
+        ranges.insert(0, (0, context));
+        ranges.push((lines.len() - context, lines.len()));
+    }
+
+    // 合并重叠或相接的区间
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    hunks_from_ranges(lines, old_offset, new_offset, merged)
+}
+
+/// Find the `[start, end)` ranges of `lines` covering each change plus its context
+fn change_ranges(lines: &[LineChange], options: &DiffOptions) -> Vec<(usize, usize)> {
+    let context = options.context_lines;
+    let ignored = if options.ignore_blank_lines {
+        blank_only_changes(lines, options)
+    } else {
+        vec![false; lines.len()]
+    };
+
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (idx, line) in lines.iter().enumerate() {
+        if matches!(line.change_type, ChangeType::Context) || ignored[idx] {
+            continue;
+        }
+
+        let start = idx.saturating_sub(context);
+        let end = (idx + 1 + context).min(lines.len());
+
+        match ranges.last_mut() {
+            // 两个改动之间的上下文不超过 2 * context 时合并
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+}
+
+/// Turn ranges of `lines` into hunks with correct headers
+fn hunks_from_ranges(
+    lines: &[LineChange],
+    old_offset: usize,
+    new_offset: usize,
+    ranges: Vec<(usize, usize)>,
+) -> Vec<Hunk> {
+    // 每个位置之前已消耗的旧/新行数，用于计算 hunk 头
+    let mut old_before = Vec::with_capacity(lines.len() + 1);
+    let mut new_before = Vec::with_capacity(lines.len() + 1);
+    let (mut old_pos, mut new_pos) = (old_offset, new_offset);
+    for line in lines {
+        old_before.push(old_pos);
+        new_before.push(new_pos);
+        if line.old_line_num.is_some() {
+            old_pos += 1;
+        }
+        if line.new_line_num.is_some() {
+            new_pos += 1;
+        }
+    }
+    old_before.push(old_pos);
+    new_before.push(new_pos);
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let old_count = old_before[end] - old_before[start];
+            let new_count = new_before[end] - new_before[start];
+
+            Hunk {
+                // 空区间按 GNU diff 约定指向其前一行
+                old_start: if old_count == 0 { old_before[start] } else { old_before[start] + 1 },
+                old_count,
+                new_start: if new_count == 0 { new_before[start] } else { new_before[start] + 1 },
+                new_count,
+                lines: lines[start..end].to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Mark changes belonging to runs that only add or remove blank lines
+fn blank_only_changes(lines: &[LineChange], options: &DiffOptions) -> Vec<bool> {
+    let mut ignored = vec![false; lines.len()];
+    let mut idx = 0;
+
+    while idx < lines.len() {
+        if matches!(lines[idx].change_type, ChangeType::Context) {
+            idx += 1;
+            continue;
+        }
+
+        let start = idx;
+        while idx < lines.len() && !matches!(lines[idx].change_type, ChangeType::Context) {
+            idx += 1;
+        }
+
+        if lines[start..idx].iter().all(|l| is_blank_line(&l.content, options)) {
+            ignored[start..idx].fill(true);
+        }
+    }
+
+    ignored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(n: usize) -> LineChange {
+        LineChange {
+            change_type: ChangeType::Context,
+            old_line_num: Some(n),
+            new_line_num: Some(n),
+            content: format!("line {}", n),
+            old_content: None,
+            inline: None,
+        }
+    }
+
+    /// 100 行，第 10 行和第 60 行被修改
+    fn sample() -> Vec<LineChange> {
+        let mut lines = Vec::new();
+        for n in 1..=100 {
+            if n == 10 || n == 60 {
+                lines.push(LineChange {
+                    change_type: ChangeType::Delete,
+                    old_line_num: Some(n),
+                    new_line_num: None,
+                    content: format!("line {}", n),
+                    old_content: None,
+                    inline: None,
+                });
+                lines.push(LineChange {
+                    change_type: ChangeType::Add,
+                    old_line_num: None,
+                    new_line_num: Some(n),
+                    content: format!("changed {}", n),
+                    old_content: None,
+                    inline: None,
+                });
+            } else {
+                lines.push(context(n));
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn test_distant_changes_split_into_hunks() {
+        let hunks = build_hunks(&sample(), 0, 0, &DiffOptions::default());
+
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((hunks[0].old_start, hunks[0].old_count), (7, 7));
+        assert_eq!((hunks[0].new_start, hunks[0].new_count), (7, 7));
+        assert_eq!(hunks[0].lines.len(), 8);
+        assert_eq!((hunks[1].old_start, hunks[1].old_count), (57, 7));
+    }
+
+    #[test]
+    fn test_large_context_merges_hunks() {
+        let options = DiffOptions {
+            context_lines: 1000,
+            ..Default::default()
+        };
+        let hunks = build_hunks(&sample(), 0, 0, &options);
+
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].old_count), (1, 100));
+        assert_eq!((hunks[0].new_start, hunks[0].new_count), (1, 100));
+    }
+
+    #[test]
+    fn test_zero_context_pure_insertion() {
+        let lines = vec![
+            context(1),
+            LineChange {
+                change_type: ChangeType::Add,
+                old_line_num: None,
+                new_line_num: Some(2),
+                content: "new".to_string(),
+                old_content: None,
+                inline: None,
+            },
+            LineChange {
+                change_type: ChangeType::Context,
+                old_line_num: Some(2),
+                new_line_num: Some(3),
+                content: "line 2".to_string(),
+                old_content: None,
+                inline: None,
+            },
+        ];
+
+        let options = DiffOptions {
+            context_lines: 0,
+            ..Default::default()
+        };
+        let hunks = build_hunks(&lines, 0, 0, &options);
+
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].old_count), (1, 0));
+        assert_eq!((hunks[0].new_start, hunks[0].new_count), (2, 1));
+    }
+
+    #[test]
+    fn test_ignore_blank_lines() {
+        let old = ["a\n", "b\n", "c\n"];
+        let new = ["a\n", "\n", "b\n", "C\n"];
+        let options = DiffOptions {
+            ignore_blank_lines: true,
+            context_lines: 0,
+            ..Default::default()
+        };
+
+        let lines = diff_lines(&old, &new, 0, 0, &options);
+        let hunks = build_hunks(&lines, 0, 0, &options);
+
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].new_start), (3, 4));
+
+        // 只有空格的行不是空行，除非同时忽略空白
+        let old = ["  \n", "c\n"];
+        let new = ["c\n"];
+        let lines = diff_lines(&old, &new, 0, 0, &options);
+        assert_eq!(build_hunks(&lines, 0, 0, &options).len(), 1);
+
+        let options = DiffOptions { ignore_space_change: true, ..options };
+        let lines = diff_lines(&old, &new, 0, 0, &options);
+        assert!(build_hunks(&lines, 0, 0, &options).is_empty());
+    }
+
+    #[test]
+    fn test_ignored_differences_keep_old_text() {
+        let old = ["Hello\n", "same\n", "x\n"];
+        let new = ["hello\n", "same\n", "y\n"];
+        let options = DiffOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+
+        // 视为相同的行保留两侧的文本，内容相同时不重复保存
+        let lines = diff_lines(&old, &new, 0, 0, &options);
+        assert!(matches!(lines[0].change_type, ChangeType::Context));
+        assert_eq!(lines[0].content, "hello");
+        assert_eq!(lines[0].old_content.as_deref(), Some("Hello"));
+        assert_eq!(lines[1].old_content, None);
+    }
+}
@@ -0,0 +1,83 @@
+use similar::{ChangeTag, TextDiff};
+
+use super::types::{InlineDiff, InlineMode, InlineSegment};
+
+/// Lines longer than this are never paired, to keep intra-line diffing cheap
+const MAX_INLINE_LINE_LEN: usize = 4096;
+
+/// Minimum similarity for a deleted/inserted pair to count as a modification
+const MIN_SIMILARITY: f32 = 0.5;
+
+/// Compute intra-line changes between an old and a new line.
+///
+/// Returns `None` when inline diffing is disabled or the lines are too
+/// different to be shown as one modified line.
+pub fn inline_diff(old: &str, new: &str, mode: InlineMode) -> Option<InlineDiff> {
+    if mode == InlineMode::None
+        || old.len() > MAX_INLINE_LINE_LEN
+        || new.len() > MAX_INLINE_LINE_LEN
+    {
+        return None;
+    }
+
+    let diff = match mode {
+        InlineMode::Word => TextDiff::from_words(old, new),
+        _ => TextDiff::from_chars(old, new),
+    };
+
+    if diff.ratio() < MIN_SIMILARITY {
+        return None;
+    }
+
+    let mut old_segments = Vec::new();
+    let mut new_segments = Vec::new();
+
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Equal => {
+                push_segment(&mut old_segments, change.value(), false);
+                push_segment(&mut new_segments, change.value(), false);
+            }
+            ChangeTag::Delete => push_segment(&mut old_segments, change.value(), true),
+            ChangeTag::Insert => push_segment(&mut new_segments, change.value(), true),
+        }
+    }
+
+    Some(InlineDiff {
+        old: old_segments,
+        new: new_segments,
+    })
+}
+
+/// Append text, merging it into the last segment when the emphasis matches
+fn push_segment(segments: &mut Vec<InlineSegment>, text: &str, emphasized: bool) {
+    match segments.last_mut() {
+        Some(last) if last.emphasized == emphasized => last.text.push_str(text),
+        _ => segments.push(InlineSegment {
+            text: text.to_string(),
+            emphasized,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_inline_diff() {
+        let inline = inline_diff("port = 8080", "port = 9090", InlineMode::Word).unwrap();
+
+        assert_eq!(inline.old.last().unwrap().text, "8080");
+        assert!(inline.old.last().unwrap().emphasized);
+        assert_eq!(inline.new.last().unwrap().text, "9090");
+        assert_eq!(inline.new[0].text, "port = ");
+        assert!(!inline.new[0].emphasized);
+    }
+
+    #[test]
+    fn test_dissimilar_lines_are_not_paired() {
+        assert!(inline_diff("fn main() {", "}", InlineMode::Char).is_none());
+        assert!(inline_diff("a = 1", "a = 2", InlineMode::None).is_none());
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use super::types::*;
+
+/// Version of the JSON output schema, see `docs/JSON_OUTPUT.md`.
+///
+/// Bump it whenever a field is renamed or removed or its meaning changes;
+/// adding optional fields does not require a bump.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// A JSON document tagged with the schema version
+#[derive(Serialize)]
+struct Versioned<'a, T: Serialize> {
+    schema_version: u32,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+/// Serialize the whole diff result as one JSON document (`--format json`)
+pub fn to_json(diff_result: &DiffResult) -> Result<String> {
+    let result = DiffResult {
+        files: diff_result.files.iter().map(without_full_content).collect(),
+        ..diff_result.clone()
+    };
+
+    Ok(serde_json::to_string_pretty(&Versioned {
+        schema_version: SCHEMA_VERSION,
+        data: &result,
+    })?)
+}
+
+/// Serialize one file diff as a single JSON line (`--format jsonl`)
+pub fn to_json_line(file_diff: &FileDiff) -> Result<String> {
+    Ok(serde_json::to_string(&Versioned {
+        schema_version: SCHEMA_VERSION,
+        data: &without_full_content(file_diff),
+    })?)
+}
+
+/// `full_content` only serves the web viewer's full-file view
+fn without_full_content(file_diff: &FileDiff) -> FileDiff {
+    FileDiff {
+        full_content: None,
+        ..file_diff.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::file::FileDiffer;
+
+    #[test]
+    fn test_json_output_is_versioned() {
+        let file_diff = FileDiffer::new(DiffOptions::default()).compare_text("a\nb\n", "a\nc\n", "old.txt", "new.txt");
+        let (total_additions, total_deletions) = file_diff.count_changes();
+        let diff_result = DiffResult {
+            files: vec![file_diff],
+            total_additions,
+            total_deletions,
+            total_files_changed: 1,
+            merge: None,
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(&diff_result).unwrap()).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["total_additions"], 1);
+        assert_eq!(json["files"][0]["old_path"], "old.txt");
+        assert!(json["files"][0].get("full_content").is_none());
+
+        let line: serde_json::Value = serde_json::from_str(&to_json_line(&diff_result.files[0]).unwrap()).unwrap();
+        assert_eq!(line["schema_version"], SCHEMA_VERSION);
+        assert_eq!(line["hunks"][0]["old_start"], 1);
+    }
+}
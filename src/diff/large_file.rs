@@ -1,29 +1,43 @@
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressStyle};
-use memmap2::Mmap;
 use memchr::Memchr;
-use similar::TextDiff;
-use std::fs::{self, File};
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 
+use super::hunk::{build_hunks, build_segment_hunks, diff_lines};
+use super::line_ending::{line_endings, missing_newline};
+use super::normalize::normalize_line;
 use super::types::*;
+use crate::utils::encoding::decode_utf8_escaped;
+use crate::utils::fs as utils_fs;
+use crate::utils::input::{self, InputData};
 
-/// 内存映射文件，用于高效处理大文件
+/// 内存映射文件，用于高效处理大文件；标准输入和管道的内容读入内存
 pub struct MmapFile {
-    mmap: Mmap,
+    data: InputData,
     line_offsets: Vec<usize>,  // 缓存行偏移量
 }
 
 impl MmapFile {
-    /// 打开文件并创建内存映射
+    /// 打开文件并创建内存映射（`-` 表示标准输入）
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let file = File::open(path)?;
-        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self::from_data(input::read_input(path)?))
+    }
+
+    /// 使用已在内存中的内容
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self::from_data(InputData::Buffered(bytes))
+    }
 
+    fn from_data(data: InputData) -> Self {
         // 计算所有行的偏移量（只扫描一次）
-        let line_offsets = Self::calculate_line_offsets(&mmap);
+        let line_offsets = Self::calculate_line_offsets(&data);
 
-        Ok(Self { mmap, line_offsets })
+        Self { data, line_offsets }
     }
 
     /// 计算所有换行符的位置
@@ -46,8 +60,8 @@ impl MmapFile {
         self.line_offsets.len()
     }
 
-    /// 获取指定行的内容
-    pub fn get_line(&self, line_num: usize) -> Option<&str> {
+    /// 获取指定行的内容，非法的 UTF-8 字节以 `\xNN` 转义显示
+    pub fn get_line(&self, line_num: usize) -> Option<Cow<'_, str>> {
         if line_num >= self.line_count() {
             return None;
         }
@@ -56,21 +70,21 @@ impl MmapFile {
         let end = if line_num + 1 < self.line_offsets.len() {
             self.line_offsets[line_num + 1] - 1  // 不包含换行符
         } else {
-            self.mmap.len()
+            self.data.len()
         };
 
         if start >= end {
-            return Some("");
+            return Some(Cow::Borrowed(""));
         }
 
-        std::str::from_utf8(&self.mmap[start..end]).ok()
+        Some(decode_utf8_escaped(&self.data[start..end]))
     }
 
     /// 获取指定范围的行
     pub fn get_lines(&self, start: usize, count: usize) -> Vec<String> {
         let end = (start + count).min(self.line_count());
         (start..end)
-            .filter_map(|i| self.get_line(i).map(|s| s.to_string()))
+            .filter_map(|i| self.get_line(i).map(Cow::into_owned))
             .collect()
     }
 
@@ -79,16 +93,105 @@ impl MmapFile {
         self.get_lines(0, self.line_count())
     }
 
+    /// 获取文本行数（不含末尾换行符之后的空行，与 `str::lines` 一致）
+    pub fn text_line_count(&self) -> usize {
+        if self.data.is_empty() || self.data.last() == Some(&b'\n') {
+            self.line_count() - 1
+        } else {
+            self.line_count()
+        }
+    }
+
+    /// 获取 [start, end) 范围的行，并保留行尾的换行符
+    ///
+    /// 保留换行符后，缺少末尾换行符的最后一行与其他行比较时不会被视为相同，
+    /// 与 `FileDiffer` 的行为一致。
+    pub fn get_terminated_lines(&self, start: usize, end: usize) -> Vec<String> {
+        (start..end.min(self.line_count()))
+            .map(|i| {
+                let mut line = self.get_line(i).unwrap_or_default().into_owned();
+                if i + 1 < self.line_count() {
+                    line.push('\n');
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// 获取文件的原始字节
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     /// 获取文件大小（字节）
     pub fn size(&self) -> usize {
-        self.mmap.len()
+        self.data.len()
+    }
+}
+
+/// Number of changed lines in a diff, counting a modified line as one
+/// deletion and one addition
+fn changed_line_count(lines: &[LineChange]) -> usize {
+    lines
+        .iter()
+        .map(|line| match line.change_type {
+            ChangeType::Context => 0,
+            ChangeType::Add | ChangeType::Delete => 1,
+            ChangeType::Modify => 2,
+        })
+        .sum()
+}
+
+/// For every old line whose content occurs exactly once in each file, the
+/// index of its counterpart in the new file.
+///
+/// Only such lines are safe anchors: a blank line or a lone `}` may be
+/// matched against the wrong copy, which would misalign everything after it.
+/// Lines are compared by a hash of their normalized content, so the text of
+/// the files is never held in memory.
+fn unique_matches(file1: &MmapFile, file2: &MmapFile, total_lines1: usize, total_lines2: usize, options: &DiffOptions) -> Vec<Option<usize>> {
+    let line_hash = |file: &MmapFile, i: usize| {
+        let mut hasher = DefaultHasher::new();
+        normalize_line(&file.get_line(i).unwrap_or_default(), options).hash(&mut hasher);
+        hasher.finish()
+    };
+    let hashes1: Vec<u64> = (0..total_lines1).into_par_iter().map(|i| line_hash(file1, i)).collect();
+    let hashes2: Vec<u64> = (0..total_lines2).into_par_iter().map(|i| line_hash(file2, i)).collect();
+
+    // 行的哈希 -> (旧文件出现次数, 新文件出现次数, 新文件中的位置)
+    let mut occurrences: HashMap<u64, (usize, usize, usize)> = HashMap::new();
+    for &hash in &hashes1 {
+        occurrences.entry(hash).or_default().0 += 1;
     }
+    for (idx, hash) in hashes2.iter().enumerate() {
+        if let Some(entry) = occurrences.get_mut(hash) {
+            entry.1 += 1;
+            entry.2 = idx;
+        }
+    }
+
+    hashes1
+        .iter()
+        .map(|hash| match occurrences[hash] {
+            (1, 1, idx) => Some(idx),
+            _ => None,
+        })
+        .collect()
 }
 
 // ============================================================================
 // 分块 Differ - 用于大文件
 // ============================================================================
 
+/// A pair of aligned line ranges `((start1, end1), (start2, end2))`
+type Segment = ((usize, usize), (usize, usize));
+
+/// The hunks of a segment and its number of changed lines
+type SegmentDiff = (Vec<Hunk>, usize);
+
+/// 找不到同步点时，窗口最多扩大到 chunk_size 的倍数
+const MAX_WINDOW_GROWTH: usize = 8;
+
 pub struct ChunkedDiffer {
     chunk_size: usize,
     options: DiffOptions,
@@ -121,6 +224,8 @@ impl ChunkedDiffer {
                 old_line_num: Some(idx + 1),
                 new_line_num: Some(idx + 1),
                 content: line.clone(),
+                old_content: None,
+                inline: None,
             }
         }).collect()
     }
@@ -133,13 +238,17 @@ impl ChunkedDiffer {
         path1: &str,
         path2: &str,
     ) -> Result<FileDiff> {
-        let total_lines1 = file1.line_count();
-        let total_lines2 = file2.line_count();
+        let total_lines1 = file1.text_line_count();
+        let total_lines2 = file2.text_line_count();
         let max_lines = total_lines1.max(total_lines2);
 
-        let mut all_hunks = Vec::new();
+        let anchors = unique_matches(file1, file2, total_lines1, total_lines2, &self.options);
+
+        let mut segments = Vec::new();
         let mut offset1 = 0;
         let mut offset2 = 0;
+        let mut window = self.chunk_size;
+        let mut jump = None;
 
         // 创建进度条（仅当显示进度且文件较大时）
         let progress = if self.show_progress && max_lines > 50_000 {
@@ -156,25 +265,59 @@ impl ChunkedDiffer {
             None
         };
 
-        // 分块处理
+        // 分块处理：每次只提交到最后一个同步点（两侧都唯一且相互匹配的行），
+        // 其后未对齐的尾部带入下一块重新对比，避免插入/删除导致后续块全部错位
         while offset1 < total_lines1 || offset2 < total_lines2 {
-            let chunk_size1 = self.chunk_size.min(total_lines1 - offset1);
-            let chunk_size2 = self.chunk_size.min(total_lines2 - offset2);
-
-            if chunk_size1 == 0 && chunk_size2 == 0 {
-                break;
-            }
-
-            let chunk1 = file1.get_lines(offset1, chunk_size1);
-            let chunk2 = file2.get_lines(offset2, chunk_size2);
+            let (end1, end2) = match jump {
+                Some((anchor1, anchor2)) => (anchor1 + 1, anchor2 + 1),
+                None => ((offset1 + window).min(total_lines1), (offset2 + window).min(total_lines2)),
+            };
+
+            let chunk1 = file1.get_terminated_lines(offset1, end1);
+            let chunk2 = file2.get_terminated_lines(offset2, end2);
+            let old_lines: Vec<&str> = chunk1.iter().map(String::as_str).collect();
+            let new_lines: Vec<&str> = chunk2.iter().map(String::as_str).collect();
+            let lines = diff_lines(&old_lines, &new_lines, offset1, offset2, &self.options);
+
+            let at_end = jump.is_some() || (end1 == total_lines1 && end2 == total_lines2);
+            let sync = if at_end {
+                None
+            } else {
+                Self::find_sync_point(&lines, &anchors, end1, end2, window / 4)
+            };
+
+            let (committed, next1, next2) = match sync {
+                Some(idx) => (
+                    &lines[..=idx],
+                    lines[idx].old_line_num.unwrap_or(end1),
+                    lines[idx].new_line_num.unwrap_or(end2),
+                ),
+                None if at_end => (&lines[..], end1, end2),
+                // 窗口内的匹配不可靠时，直接对比到窗口内最后一个锚点；窗口内没有锚点时
+                // 扩大窗口，超过上限后对比到下一个锚点，再没有锚点时整块提交
+                None => {
+                    let anchor = Self::last_anchor(&anchors, offset1, offset2, end1, end2);
+                    if anchor.is_none() && window < self.chunk_size * MAX_WINDOW_GROWTH {
+                        window *= 2;
+                        continue;
+                    }
+                    match anchor.or_else(|| Self::next_anchor(&anchors, offset1, offset2)) {
+                        Some(anchor) => {
+                            jump = Some(anchor);
+                            continue;
+                        }
+                        None => (&lines[..], end1, end2),
+                    }
+                }
+            };
 
-            // 对小块进行 diff
-            if let Some(chunk_hunks) = self.diff_chunk(&chunk1, &chunk2, offset1, offset2) {
-                all_hunks.push(chunk_hunks);
-            }
+            let diff = (build_segment_hunks(committed, offset1, offset2, &self.options), changed_line_count(committed));
+            self.push_segment(&mut segments, ((offset1, next1), (offset2, next2)), diff, file1, file2);
 
-            offset1 += chunk_size1;
-            offset2 += chunk_size2;
+            offset1 = next1;
+            offset2 = next2;
+            window = self.chunk_size;
+            jump = None;
 
             // 更新进度
             if let Some(ref pb) = progress {
@@ -188,10 +331,7 @@ impl ChunkedDiffer {
         }
 
         // 合并相邻的 hunks
-        let merged_hunks = self.merge_hunks(all_hunks);
-
-        // 计算统计信息
-        let (additions, deletions) = self.count_changes(&merged_hunks);
+        let merged_hunks = self.merge_hunks(segments.into_iter().flat_map(|(_, (hunks, _))| hunks).collect());
 
         // 生成完整文件内容（用于 Web 全文展示）
         let full_content = Some(self.generate_full_content(file2));
@@ -205,98 +345,141 @@ impl ChunkedDiffer {
             is_deleted: false,
             hunks: merged_hunks,
             full_content,
+            binary_diff: None,
+            encodings: None,
+            line_endings: line_endings(file1.as_bytes(), file2.as_bytes(), &self.options),
+            missing_newline: missing_newline(file1.as_bytes(), file2.as_bytes()),
+            modes: None,
+            mtimes: None,
+            status: FileStatus::Modified,
+            similarity: None,
+            error: None,
+        })
+    }
+
+    /// The first anchor at or after `offset1` whose counterpart lies at or
+    /// after `offset2`, as 0-based `(old, new)` indices.
+    fn next_anchor(anchors: &[Option<usize>], offset1: usize, offset2: usize) -> Option<(usize, usize)> {
+        anchors[offset1..]
+            .iter()
+            .enumerate()
+            .find_map(|(i, anchor)| anchor.filter(|&j| j >= offset2).map(|j| (offset1 + i, j)))
+    }
+
+    /// The last anchor of an in-order chain of anchors inside the window
+    /// `[offset1, end1) x [offset2, end2)`, as 0-based `(old, new)` indices.
+    fn last_anchor(anchors: &[Option<usize>], offset1: usize, offset2: usize, end1: usize, end2: usize) -> Option<(usize, usize)> {
+        let mut last = None;
+        let mut next2 = offset2;
+        for (i, anchor) in anchors.iter().enumerate().take(end1).skip(offset1) {
+            if let Some(j) = *anchor
+                && (next2..end2).contains(&j)
+            {
+                last = Some((i, j));
+                next2 = j + 1;
+            }
+        }
+        last
+    }
+
+    /// Find the last anchor matched on both sides that lies at least `margin`
+    /// lines before the end of the window, so the tail is re-diffed with the
+    /// next chunk.
+    fn find_sync_point(lines: &[LineChange], anchors: &[Option<usize>], end1: usize, end2: usize, margin: usize) -> Option<usize> {
+        let limit1 = end1.saturating_sub(margin);
+        let limit2 = end2.saturating_sub(margin);
+
+        lines.iter().rposition(|line| {
+            matches!(line.change_type, ChangeType::Context)
+                && line.old_line_num.is_some_and(|n| n <= limit1 && anchors[n - 1].is_some())
+                && line.new_line_num.is_some_and(|n| n <= limit2)
         })
     }
 
+    /// 对比一对分段，同时返回改动的行数
     fn diff_chunk(
         &self,
         chunk1: &[String],
         chunk2: &[String],
         offset1: usize,
         offset2: usize,
-    ) -> Option<Hunk> {
+    ) -> SegmentDiff {
         if chunk1.is_empty() && chunk2.is_empty() {
-            return None;
+            return (Vec::new(), 0);
         }
 
-        let text1 = chunk1.join("\n");
-        let text2 = chunk2.join("\n");
+        let old_lines: Vec<&str> = chunk1.iter().map(String::as_str).collect();
+        let new_lines: Vec<&str> = chunk2.iter().map(String::as_str).collect();
+        let lines = diff_lines(&old_lines, &new_lines, offset1, offset2, &self.options);
 
-        let diff = TextDiff::from_lines(&text1, &text2);
-
-        let mut lines = Vec::new();
-        let mut old_line = offset1 + 1;
-        let mut new_line = offset2 + 1;
-
-        for change in diff.iter_all_changes() {
-            let content = change.value().trim_end_matches(&['\n', '\r'][..]).to_string();
+        (build_segment_hunks(&lines, offset1, offset2, &self.options), changed_line_count(&lines))
+    }
 
-            match change.tag() {
-                similar::ChangeTag::Equal => {
-                    lines.push(LineChange {
-                        change_type: ChangeType::Context,
-                        old_line_num: Some(old_line),
-                        new_line_num: Some(new_line),
-                        content,
-                    });
-                    old_line += 1;
-                    new_line += 1;
-                }
-                similar::ChangeTag::Delete => {
-                    lines.push(LineChange {
-                        change_type: ChangeType::Delete,
-                        old_line_num: Some(old_line),
-                        new_line_num: None,
-                        content,
-                    });
-                    old_line += 1;
-                }
-                similar::ChangeTag::Insert => {
-                    lines.push(LineChange {
-                        change_type: ChangeType::Add,
-                        old_line_num: None,
-                        new_line_num: Some(new_line),
-                        content,
-                    });
-                    new_line += 1;
-                }
+    /// Append a diffed segment, first merging it with the segments before it
+    /// while diffing them together changes fewer lines.
+    ///
+    /// The shortest diff may go around an anchor, deleting that unique line to
+    /// keep more of the repeated lines around it. A segment without changes is
+    /// a common prefix or suffix of the merged pair, so it never needs checking.
+    fn push_segment(
+        &self,
+        segments: &mut Vec<(Segment, SegmentDiff)>,
+        mut segment: Segment,
+        mut diff: SegmentDiff,
+        file1: &MmapFile,
+        file2: &MmapFile,
+    ) {
+        while let Some((previous, (_, previous_changed))) = segments.last()
+            && *previous_changed > 0
+            && diff.1 > 0
+        {
+            let merged = ((previous.0.0, segment.0.1), (previous.1.0, segment.1.1));
+            let merged_diff = self.diff_segment(file1, file2, &merged);
+            if merged_diff.1 >= previous_changed + diff.1 {
+                break;
             }
+            segments.pop();
+            segment = merged;
+            diff = merged_diff;
         }
-
-        if lines.is_empty() {
-            return None;
-        }
-
-        Some(Hunk {
-            old_start: offset1 + 1,
-            old_count: chunk1.len(),
-            new_start: offset2 + 1,
-            new_count: chunk2.len(),
-            lines,
-        })
+        segments.push((segment, diff));
     }
 
-    fn merge_hunks(&self, hunks: Vec<Hunk>) -> Vec<Hunk> {
-        // 简单实现：直接返回所有 hunks
-        // TODO: 实现智能合并相邻的 hunks
-        hunks
+    fn diff_segment(&self, file1: &MmapFile, file2: &MmapFile, &((start1, end1), (start2, end2)): &Segment) -> SegmentDiff {
+        let chunk1 = file1.get_terminated_lines(start1, end1);
+        let chunk2 = file2.get_terminated_lines(start2, end2);
+        self.diff_chunk(&chunk1, &chunk2, start1, start2)
     }
 
-    fn count_changes(&self, hunks: &[Hunk]) -> (usize, usize) {
-        let mut additions = 0;
-        let mut deletions = 0;
+    /// 合并各分段产生的 hunks
+    ///
+    /// 分段 hunk 保留了分段首尾的上下文，首尾相接的 hunk 先拼接成连续的行序列，
+    /// 再按 context_lines 重新裁剪和分组，从而跨分段边界合并 hunk 并重新计算行号范围。
+    fn merge_hunks(&self, hunks: Vec<Hunk>) -> Vec<Hunk> {
+        let mut merged = Vec::new();
+        let mut run: Vec<LineChange> = Vec::new();
+        let (mut run_old, mut run_new) = (0, 0); // 连续序列之前的行数
+        let (mut end_old, mut end_new) = (0, 0); // 连续序列结束的位置
 
         for hunk in hunks {
-            for line in &hunk.lines {
-                match line.change_type {
-                    ChangeType::Add => additions += 1,
-                    ChangeType::Delete => deletions += 1,
-                    _ => {}
-                }
+            let old_before = if hunk.old_count == 0 { hunk.old_start } else { hunk.old_start - 1 };
+            let new_before = if hunk.new_count == 0 { hunk.new_start } else { hunk.new_start - 1 };
+
+            // 与前一个 hunk 不相接时，先输出已拼接的序列
+            if run.is_empty() || old_before != end_old || new_before != end_new {
+                merged.extend(build_hunks(&run, run_old, run_new, &self.options));
+                run.clear();
+                run_old = old_before;
+                run_new = new_before;
             }
+
+            end_old = old_before + hunk.old_count;
+            end_new = new_before + hunk.new_count;
+            run.extend(hunk.lines);
         }
+        merged.extend(build_hunks(&run, run_old, run_new, &self.options));
 
-        (additions, deletions)
+        merged
     }
 }
 
@@ -304,8 +487,6 @@ impl ChunkedDiffer {
 // 并行 Differ - 利用多核加速
 // ============================================================================
 
-use rayon::prelude::*;
-
 pub struct ParallelDiffer {
     chunk_size: usize,
     options: DiffOptions,
@@ -337,16 +518,16 @@ impl ParallelDiffer {
         path1: &str,
         path2: &str,
     ) -> Result<FileDiff> {
-        let total_lines1 = file1.line_count();
-        let total_lines2 = file2.line_count();
+        let total_lines1 = file1.text_line_count();
+        let total_lines2 = file2.text_line_count();
         let max_lines = total_lines1.max(total_lines2);
 
-        // 计算分块数量
-        let num_chunks = (max_lines + self.chunk_size - 1) / self.chunk_size;
+        // 用两侧都唯一的行作为锚点切分文件，保证每一对分段彼此对齐
+        let mut segments = self.find_segments(file1, file2, total_lines1, total_lines2);
 
         // 创建进度条（仅当显示进度且文件较大时）
         let progress = if self.show_progress && max_lines > 50_000 {
-            let pb = ProgressBar::new(num_chunks as u64);
+            let pb = ProgressBar::new(segments.len() as u64);
             pb.set_style(
                 ProgressStyle::default_bar()
                     .template("[{elapsed_precise}] {bar:40.green/blue} {pos:>4}/{len:4} chunks {msg}")
@@ -359,25 +540,13 @@ impl ParallelDiffer {
             None
         };
 
-        // 并行处理每个块
-        let hunks: Vec<Option<Hunk>> = (0..num_chunks)
-            .into_par_iter()
-            .map(|i| {
-                let start1 = (i * self.chunk_size).min(total_lines1);
-                let start2 = (i * self.chunk_size).min(total_lines2);
-
-                let count1 = self.chunk_size.min(total_lines1 - start1);
-                let count2 = self.chunk_size.min(total_lines2 - start2);
-
-                if count1 == 0 && count2 == 0 {
-                    return None;
-                }
-
-                let chunk1 = file1.get_lines(start1, count1);
-                let chunk2 = file2.get_lines(start2, count2);
-
-                let chunked = ChunkedDiffer::new(self.chunk_size, self.options.clone());
-                let result = chunked.diff_chunk(&chunk1, &chunk2, start1, start2);
+        // 并行处理每个分段
+        let chunked = ChunkedDiffer::new(self.chunk_size, self.options.clone());
+        let diff_segment = |segment: &Segment| chunked.diff_segment(file1, file2, segment);
+        let mut diffs: Vec<SegmentDiff> = segments
+            .par_iter()
+            .map(|segment| {
+                let result = diff_segment(segment);
 
                 // 更新进度（线程安全）
                 if let Some(ref pb) = progress {
@@ -388,17 +557,55 @@ impl ParallelDiffer {
             })
             .collect();
 
+        // 最小 diff 可能绕过某个锚点：删掉这行唯一的行，换来周围更多重复行相同。
+        // 合并锚点两侧的分段后改动更少时去掉该锚点，直到每个锚点都不再增加改动。
+        // 一侧没有改动的锚点处在公共前缀或后缀中，不会被绕过，无需检查
+        let mut fresh = vec![true; segments.len()];
+        loop {
+            let mut merges: Vec<Option<(Segment, SegmentDiff)>> = (1..segments.len())
+                .into_par_iter()
+                .map(|k| {
+                    let (before, after) = (diffs[k - 1].1, diffs[k].1);
+                    if !(fresh[k - 1] || fresh[k]) || before == 0 || after == 0 {
+                        return None;
+                    }
+                    let merged = ((segments[k - 1].0.0, segments[k].0.1), (segments[k - 1].1.0, segments[k].1.1));
+                    let diff = diff_segment(&merged);
+                    (diff.1 < before + after).then_some((merged, diff))
+                })
+                .collect();
+            if merges.iter().all(Option::is_none) {
+                break;
+            }
+
+            let mut next = Vec::with_capacity(segments.len());
+            let mut k = 0;
+            while k < segments.len() {
+                match merges.get_mut(k).and_then(Option::take) {
+                    Some((segment, diff)) => {
+                        next.push((segment, diff, true));
+                        k += 2;
+                    }
+                    None => {
+                        next.push((segments[k], std::mem::take(&mut diffs[k]), false));
+                        k += 1;
+                    }
+                }
+            }
+            (segments, diffs, fresh) = (
+                next.iter().map(|entry| entry.0).collect(),
+                next.iter_mut().map(|entry| std::mem::take(&mut entry.1)).collect(),
+                next.iter().map(|entry| entry.2).collect(),
+            );
+        }
+
         // 完成进度条
         if let Some(pb) = progress {
             pb.finish_with_message("Parallel processing complete!");
         }
 
-        // 合并结果
-        let merged_hunks: Vec<Hunk> = hunks.into_iter().flatten().collect();
-
-        // 计算统计信息
-        let chunked = ChunkedDiffer::new(self.chunk_size, self.options.clone());
-        let (additions, deletions) = chunked.count_changes(&merged_hunks);
+        // 合并相邻分段的 hunks
+        let merged_hunks = chunked.merge_hunks(diffs.into_iter().flat_map(|(hunks, _)| hunks).collect());
 
         // 生成完整文件内容（用于 Web 全文展示）
         let full_content = Some(chunked.generate_full_content(file2));
@@ -412,8 +619,84 @@ impl ParallelDiffer {
             is_deleted: false,
             hunks: merged_hunks,
             full_content,
+            binary_diff: None,
+            encodings: None,
+            line_endings: line_endings(file1.as_bytes(), file2.as_bytes(), &self.options),
+            missing_newline: missing_newline(file1.as_bytes(), file2.as_bytes()),
+            modes: None,
+            mtimes: None,
+            status: FileStatus::Modified,
+            similarity: None,
+            error: None,
         })
     }
+
+    /// Split both files into aligned segments `((start1, end1), (start2, end2))`.
+    ///
+    /// Segments start at lines that occur exactly once in each file and lie on
+    /// their longest in-order chain, as in patience diff: a unique line that
+    /// moved is left out instead of cutting off every anchor after it. The
+    /// first such line at or after every `chunk_size` lines of the old file
+    /// starts a new segment.
+    fn find_segments(
+        &self,
+        file1: &MmapFile,
+        file2: &MmapFile,
+        total_lines1: usize,
+        total_lines2: usize,
+    ) -> Vec<Segment> {
+        let matches = unique_matches(file1, file2, total_lines1, total_lines2, &self.options);
+
+        let mut anchors = vec![(0, 0)];
+        let mut boundary = self.chunk_size;
+        for (i, j) in longest_match_chain(&matches) {
+            if i >= boundary {
+                anchors.push((i, j));
+                boundary = i + self.chunk_size;
+            }
+        }
+        anchors.push((total_lines1, total_lines2));
+
+        anchors
+            .windows(2)
+            .map(|w| ((w[0].0, w[1].0), (w[0].1, w[1].1)))
+            .collect()
+    }
+}
+
+/// The longest chain of unique-line matches that is in order in both files,
+/// as `(old, new)` indices: patience diff's longest increasing subsequence
+/// of the new-side indices, taken in old-file order.
+fn longest_match_chain(matches: &[Option<usize>]) -> Vec<(usize, usize)> {
+    let pairs: Vec<(usize, usize)> = matches
+        .iter()
+        .enumerate()
+        .filter_map(|(i, j)| j.map(|j| (i, j)))
+        .collect();
+
+    // tails[k]：长度为 k + 1 的递增链中，末尾新文件位置最小的那一对
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; pairs.len()];
+    for (p, &(_, j)) in pairs.iter().enumerate() {
+        let k = tails.partition_point(|&t| pairs[t].1 < j);
+        if k > 0 {
+            previous[p] = Some(tails[k - 1]);
+        }
+        if k == tails.len() {
+            tails.push(p);
+        } else {
+            tails[k] = p;
+        }
+    }
+
+    let mut chain = Vec::with_capacity(tails.len());
+    let mut next = tails.last().copied();
+    while let Some(p) = next {
+        chain.push(pairs[p]);
+        next = previous[p];
+    }
+    chain.reverse();
+    chain
 }
 
 // ============================================================================
@@ -461,9 +744,25 @@ impl AdaptiveDiffer {
     }
 
     /// 自适应对比文件
+    ///
+    /// `-` 表示标准输入；管道（如 `/dev/fd/63`）只能读取一次，因此两侧内容
+    /// 都只读取一次，之后的策略都在内存或映射上进行。
     pub fn diff_files(&self, path1: &str, path2: &str) -> Result<FileDiff> {
-        let size1 = fs::metadata(path1)?.len();
-        let size2 = fs::metadata(path2)?.len();
+        if input::is_stdin(path1) && input::is_stdin(path2) {
+            anyhow::bail!("Standard input can only be compared once");
+        }
+
+        let file1 = MmapFile::open(path1)?;
+        let file2 = MmapFile::open(path2)?;
+        let mut file_diff = self.diff_inputs(&file1, &file2, path1, path2)?;
+        file_diff.mtimes = Some(FileTimes::of(path1, path2));
+        Ok(file_diff)
+    }
+
+    /// 自适应对比已打开的内容，`path1` 和 `path2` 只用于显示
+    pub fn diff_inputs(&self, file1: &MmapFile, file2: &MmapFile, path1: &str, path2: &str) -> Result<FileDiff> {
+        let size1 = file1.size() as u64;
+        let size2 = file2.size() as u64;
         let max_size = size1.max(size2);
 
         tracing::info!(
@@ -471,62 +770,55 @@ impl AdaptiveDiffer {
             path1, size1, path2, size2
         );
 
+        // 二进制文件不按行对比，交给 FileDiffer 处理
+        if utils_fs::is_binary(file1.as_bytes()) || utils_fs::is_binary(file2.as_bytes()) {
+            return self.diff_small_files(file1, file2, path1, path2);
+        }
+
         match max_size {
             // 小文件：使用现有的快速方法
             s if s < self.small_file_threshold => {
                 tracing::info!("Using fast diff for small files");
-                self.diff_small_files(path1, path2)
+                self.diff_small_files(file1, file2, path1, path2)
             }
 
             // 中等文件：内存映射 + 分块
             s if s < self.medium_file_threshold => {
                 tracing::info!("Using chunked diff for medium files");
-                self.diff_medium_files(path1, path2)
+                let chunked = ChunkedDiffer::with_progress(
+                    self.chunk_size,
+                    self.options.clone(),
+                    self.show_progress
+                );
+                chunked.diff_large_files(file1, file2, path1, path2)
             }
 
             // 大文件：内存映射 + 分块 + 并行
             _ => {
                 tracing::info!("Using parallel diff for large files");
-                self.diff_large_files(path1, path2)
+                let parallel = ParallelDiffer::with_progress(
+                    self.chunk_size,
+                    self.options.clone(),
+                    self.show_progress
+                );
+                parallel.diff_parallel(file1, file2, path1, path2)
             }
         }
     }
 
-    fn diff_small_files(&self, path1: &str, path2: &str) -> Result<FileDiff> {
+    fn diff_small_files(&self, file1: &MmapFile, file2: &MmapFile, path1: &str, path2: &str) -> Result<FileDiff> {
         // 使用现有的 FileDiffer
         use super::file::FileDiffer;
         let differ = FileDiffer::new(self.options.clone());
-        differ.compare_files(path1, path2)
-    }
-
-    fn diff_medium_files(&self, path1: &str, path2: &str) -> Result<FileDiff> {
-        let file1 = MmapFile::open(path1)?;
-        let file2 = MmapFile::open(path2)?;
-
-        let chunked = ChunkedDiffer::with_progress(
-            self.chunk_size,
-            self.options.clone(),
-            self.show_progress
-        );
-        chunked.diff_large_files(&file1, &file2, path1, path2)
-    }
-
-    fn diff_large_files(&self, path1: &str, path2: &str) -> Result<FileDiff> {
-        let file1 = MmapFile::open(path1)?;
-        let file2 = MmapFile::open(path2)?;
-
-        let parallel = ParallelDiffer::with_progress(
-            self.chunk_size,
-            self.options.clone(),
-            self.show_progress
-        );
-        parallel.diff_parallel(&file1, &file2, path1, path2)
+        differ.compare_bytes(file1.as_bytes(), file2.as_bytes(), path1, path2)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diff::apply::apply_hunks;
+    use crate::diff::file::FileDiffer;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -543,9 +835,9 @@ mod tests {
         let mmap_file = MmapFile::open(temp_file.path()).unwrap();
 
         assert_eq!(mmap_file.line_count(), 4);  // 3 行 + 最后的空行
-        assert_eq!(mmap_file.get_line(0), Some("line 1"));
-        assert_eq!(mmap_file.get_line(1), Some("line 2"));
-        assert_eq!(mmap_file.get_line(2), Some("line 3"));
+        assert_eq!(mmap_file.get_line(0).as_deref(), Some("line 1"));
+        assert_eq!(mmap_file.get_line(1).as_deref(), Some("line 2"));
+        assert_eq!(mmap_file.get_line(2).as_deref(), Some("line 3"));
     }
 
     #[test]
@@ -564,4 +856,227 @@ mod tests {
         assert_eq!(lines[1], "line 4");
         assert_eq!(lines[2], "line 5");
     }
+
+    /// 简单的 xorshift 伪随机数生成器，保证测试可复现
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    /// 生成一对文件内容：旧文件每行唯一，新文件在其上随机插入、删除、修改
+    fn generate_pair(seed: u64) -> (String, String) {
+        let mut rng = Rng(seed);
+        let old: Vec<String> = (0..400 + rng.next(400)).map(|i| format!("line {} of seed {}", i, seed)).collect();
+
+        let mut new = Vec::new();
+        let mut fresh = 0;
+        for line in &old {
+            match rng.next(40) {
+                0 => {}
+                1 => new.push(format!("{} modified", line)),
+                2 => {
+                    // 偶尔插入比分块更大的整段内容
+                    for _ in 0..1 + rng.next(60) {
+                        new.push(format!("inserted {}", fresh));
+                        fresh += 1;
+                    }
+                    new.push(line.clone());
+                }
+                _ => new.push(line.clone()),
+            }
+        }
+
+        (old.join("\n") + "\n", new.join("\n") + "\n")
+    }
+
+    /// 生成一对类似源代码的文件：大量重复的空行和括号，只有少数行唯一，
+    /// 用于检验重复行附近的同步点和分段锚点
+    fn generate_repetitive_pair(seed: u64) -> (String, String) {
+        const REPEATED: [&str; 6] = ["", "}", "{", "    }", "        return;", "// ----"];
+        let mut rng = Rng(seed);
+        let old: Vec<String> = (0..400 + rng.next(400))
+            .map(|i| match rng.next(8) {
+                0 => format!("fn item_{}() {{", i),
+                n => REPEATED[n % REPEATED.len()].to_string(),
+            })
+            .collect();
+
+        let mut new = Vec::new();
+        for line in &old {
+            match rng.next(30) {
+                0 => {}
+                1 => new.push(REPEATED[rng.next(REPEATED.len())].to_string()),
+                2 => {
+                    for _ in 0..1 + rng.next(40) {
+                        new.push(REPEATED[rng.next(REPEATED.len())].to_string());
+                    }
+                    new.push(line.clone());
+                }
+                _ => new.push(line.clone()),
+            }
+        }
+
+        (old.join("\n") + "\n", new.join("\n") + "\n")
+    }
+
+    /// 把 hunks 还原成被删除的旧行号和新增的新行号集合
+    fn changed_lines(hunks: &[Hunk]) -> (Vec<usize>, Vec<usize>) {
+        let mut deleted = Vec::new();
+        let mut added = Vec::new();
+
+        for line in hunks.iter().flat_map(|h| &h.lines) {
+            match line.change_type {
+                ChangeType::Delete => deleted.push(line.old_line_num.unwrap()),
+                ChangeType::Add => added.push(line.new_line_num.unwrap()),
+                ChangeType::Modify => {
+                    deleted.push(line.old_line_num.unwrap());
+                    added.push(line.new_line_num.unwrap());
+                }
+                ChangeType::Context => {}
+            }
+        }
+
+        deleted.sort_unstable();
+        added.sort_unstable();
+        (deleted, added)
+    }
+
+    fn hunk_headers(hunks: &[Hunk]) -> Vec<(usize, usize, usize, usize)> {
+        hunks
+            .iter()
+            .map(|h| (h.old_start, h.old_count, h.new_start, h.new_count))
+            .collect()
+    }
+
+    #[test]
+    fn test_large_file_engines_match_file_differ() {
+        for seed in 1..=25 {
+            let (old_text, new_text) = generate_pair(seed);
+
+            let mut old_file = NamedTempFile::new().unwrap();
+            let mut new_file = NamedTempFile::new().unwrap();
+            old_file.write_all(old_text.as_bytes()).unwrap();
+            new_file.write_all(new_text.as_bytes()).unwrap();
+            let file1 = MmapFile::open(old_file.path()).unwrap();
+            let file2 = MmapFile::open(new_file.path()).unwrap();
+
+            let options = DiffOptions::default();
+            let expected = FileDiffer::new(options.clone()).compare_text(&old_text, &new_text, "a", "b");
+
+            let chunked = ChunkedDiffer::new(16, options.clone())
+                .diff_large_files(&file1, &file2, "a", "b")
+                .unwrap();
+            let parallel = ParallelDiffer::new(16, options)
+                .diff_parallel(&file1, &file2, "a", "b")
+                .unwrap();
+
+            assert_eq!(changed_lines(&chunked.hunks), changed_lines(&expected.hunks), "chunked, seed {}", seed);
+            assert_eq!(changed_lines(&parallel.hunks), changed_lines(&expected.hunks), "parallel, seed {}", seed);
+            assert_eq!(hunk_headers(&chunked.hunks), hunk_headers(&expected.hunks), "chunked, seed {}", seed);
+            assert_eq!(hunk_headers(&parallel.hunks), hunk_headers(&expected.hunks), "parallel, seed {}", seed);
+        }
+    }
+
+    /// 重复行很多时，长度相同的最小 diff 不止一种，分段对比选中的行可能与整体对比不同，
+    /// 因此比较改动的行数，并检查 hunks 能在原位置把旧文件变成新文件
+    #[test]
+    fn test_large_file_engines_handle_repeated_lines() {
+        let changed = |hunks: &[Hunk]| {
+            let (deleted, added) = changed_lines(hunks);
+            deleted.len() + added.len()
+        };
+
+        for seed in 1..=25 {
+            let (old_text, new_text) = generate_repetitive_pair(seed);
+            let file1 = MmapFile::from_bytes(old_text.clone().into_bytes());
+            let file2 = MmapFile::from_bytes(new_text.clone().into_bytes());
+
+            let options = DiffOptions::default();
+            let expected = FileDiffer::new(options.clone()).compare_text(&old_text, &new_text, "a", "b");
+
+            let chunked = ChunkedDiffer::new(16, options.clone())
+                .diff_large_files(&file1, &file2, "a", "b")
+                .unwrap();
+            let parallel = ParallelDiffer::new(16, options)
+                .diff_parallel(&file1, &file2, "a", "b")
+                .unwrap();
+
+            for (engine, diff) in [("chunked", &chunked), ("parallel", &parallel)] {
+                let (patched, outcomes) = apply_hunks(&old_text, diff, 0);
+                assert_eq!(patched, new_text, "{}, seed {}", engine, seed);
+                assert!(
+                    outcomes.iter().all(|outcome| matches!(outcome, HunkOutcome::Applied { offset: 0, fuzz: 0, .. })),
+                    "{}, seed {}",
+                    engine,
+                    seed
+                );
+            }
+
+            for (engine, diff) in [("chunked", &chunked), ("parallel", &parallel)] {
+                assert_eq!(changed(&diff.hunks), changed(&expected.hunks), "{}, seed {}", engine, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_insertion_does_not_shift_later_chunks() {
+        let old: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+        let new = format!("inserted\n{}", old);
+
+        let mut old_file = NamedTempFile::new().unwrap();
+        let mut new_file = NamedTempFile::new().unwrap();
+        old_file.write_all(old.as_bytes()).unwrap();
+        new_file.write_all(new.as_bytes()).unwrap();
+        let file1 = MmapFile::open(old_file.path()).unwrap();
+        let file2 = MmapFile::open(new_file.path()).unwrap();
+
+        let diff = ChunkedDiffer::new(100, DiffOptions::default())
+            .diff_large_files(&file1, &file2, "a", "b")
+            .unwrap();
+
+        assert_eq!(changed_lines(&diff.hunks), (vec![], vec![1]));
+    }
+
+    #[test]
+    fn test_moved_line_does_not_collapse_segments() {
+        // 第 100 行移到了文件末尾：以它为锚点会让之后的锚点都失效，只剩一个巨大的分段
+        let old: Vec<String> = (0..1000).map(|i| format!("line {}\n", i)).collect();
+        let mut new = old.clone();
+        let moved = new.remove(100);
+        new.push(moved);
+        let (old_text, new_text) = (old.concat(), new.concat());
+        let file1 = MmapFile::from_bytes(old_text.clone().into_bytes());
+        let file2 = MmapFile::from_bytes(new_text.clone().into_bytes());
+
+        let differ = ParallelDiffer::new(100, DiffOptions::default());
+        let segments = differ.find_segments(&file1, &file2, 1000, 1000);
+        assert_eq!(segments.len(), 10);
+        assert!(segments.iter().all(|&((start1, end1), _)| end1 - start1 <= 101));
+
+        let expected = FileDiffer::new(DiffOptions::default()).compare_text(&old_text, &new_text, "a", "b");
+        let diff = differ.diff_parallel(&file1, &file2, "a", "b").unwrap();
+        assert_eq!(changed_lines(&diff.hunks), changed_lines(&expected.hunks));
+    }
+
+    #[test]
+    fn test_in_memory_inputs_use_every_strategy() {
+        let (old_text, new_text) = generate_pair(7);
+        let file1 = MmapFile::from_bytes(old_text.clone().into_bytes());
+        let file2 = MmapFile::from_bytes(new_text.clone().into_bytes());
+        let expected = FileDiffer::new(DiffOptions::default()).compare_text(&old_text, &new_text, "-", "b");
+
+        // 阈值依次让输入走小文件、分块和并行三种策略
+        for (small, medium) in [(u64::MAX, u64::MAX), (0, u64::MAX), (0, 0)] {
+            let differ = AdaptiveDiffer::with_thresholds(DiffOptions::default(), small, medium, 16);
+            let diff = differ.diff_inputs(&file1, &file2, "-", "b").unwrap();
+            assert_eq!(changed_lines(&diff.hunks), changed_lines(&expected.hunks), "thresholds {} {}", small, medium);
+            assert_eq!(diff.old_path, "-");
+        }
+    }
 }
@@ -0,0 +1,76 @@
+use memchr::{memchr_iter, memmem};
+
+use super::types::{DiffOptions, LineEnding, LineEndings, MissingNewline};
+
+/// Detect the line terminator style of a text, `None` if it has no newline
+pub fn detect_line_ending(text: &[u8]) -> Option<LineEnding> {
+    let lf = memchr_iter(b'\n', text).count();
+    let crlf = memmem::find_iter(text, b"\r\n").count();
+
+    match (lf, crlf) {
+        (0, _) => None,
+        (_, 0) => Some(LineEnding::Lf),
+        (lf, crlf) if lf == crlf => Some(LineEnding::Crlf),
+        _ => Some(LineEnding::Mixed),
+    }
+}
+
+/// Line terminator styles of both sides, only when they differ.
+///
+/// Options that ignore CR differences (`--strip-trailing-cr`, `-w`, `-b`)
+/// also suppress the report.
+pub fn line_endings(old: &[u8], new: &[u8], options: &DiffOptions) -> Option<LineEndings> {
+    if options.strip_trailing_cr || options.ignore_whitespace || options.ignore_space_change {
+        return None;
+    }
+
+    match (detect_line_ending(old), detect_line_ending(new)) {
+        (Some(old), Some(new)) if old != new => Some(LineEndings { old, new }),
+        _ => None,
+    }
+}
+
+/// Number of the last line when the text does not end with a newline
+pub fn last_line_without_newline(text: &[u8]) -> Option<usize> {
+    if text.is_empty() || text.ends_with(b"\n") {
+        return None;
+    }
+    Some(memchr_iter(b'\n', text).count() + 1)
+}
+
+/// Lines without trailing newline on either side, `None` if both end properly
+pub fn missing_newline(old: &[u8], new: &[u8]) -> Option<MissingNewline> {
+    let old = last_line_without_newline(old);
+    let new = last_line_without_newline(new);
+
+    (old.is_some() || new.is_some()).then_some(MissingNewline { old, new })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_line_ending() {
+        assert_eq!(detect_line_ending(b"a\nb\n"), Some(LineEnding::Lf));
+        assert_eq!(detect_line_ending(b"a\r\nb\r\n"), Some(LineEnding::Crlf));
+        assert_eq!(detect_line_ending(b"a\r\nb\n"), Some(LineEnding::Mixed));
+        assert_eq!(detect_line_ending(b"no newline"), None);
+
+        let options = DiffOptions::default();
+        assert!(line_endings(b"a\n", b"a\n", &options).is_none());
+        assert_eq!(
+            line_endings(b"a\n", b"a\r\n", &options),
+            Some(LineEndings { old: LineEnding::Lf, new: LineEnding::Crlf })
+        );
+    }
+
+    #[test]
+    fn test_missing_newline() {
+        assert_eq!(last_line_without_newline(b"a\nb"), Some(2));
+        assert_eq!(last_line_without_newline(b"a\nb\n"), None);
+        assert_eq!(last_line_without_newline(b""), None);
+        assert_eq!(missing_newline(b"a\n", b"a"), Some(MissingNewline { old: None, new: Some(1) }));
+        assert_eq!(missing_newline(b"a\n", b"a\n"), None);
+    }
+}
@@ -0,0 +1,417 @@
+use anyhow::{Context, Result, bail};
+use std::ops::Range;
+
+use super::file::FileDiffer;
+use super::hunk::diff_lines;
+use super::normalize::normalize_line;
+use super::types::*;
+use crate::utils::{fs as utils_fs, input};
+
+/// Merge two files derived from a common base, like `diff3 -m` or
+/// `git merge-file`
+pub struct Merger {
+    file_differ: FileDiffer,
+    options: DiffOptions,
+    conflict_style: ConflictStyle,
+    labels: Vec<String>,
+}
+
+/// A region of the three files, as line index ranges
+struct Region {
+    kind: MergeChunkKind,
+    base: Range<usize>,
+    ours: Range<usize>,
+    theirs: Range<usize>,
+}
+
+impl Merger {
+    pub fn new(options: DiffOptions) -> Self {
+        Self {
+            file_differ: FileDiffer::new(options.clone()),
+            options,
+            conflict_style: ConflictStyle::default(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// How conflicts are written in the merged text
+    pub fn conflict_style(mut self, conflict_style: ConflictStyle) -> Self {
+        self.conflict_style = conflict_style;
+        self
+    }
+
+    /// Names to use instead of the file names, in the order ours, base,
+    /// theirs (like `git merge-file -L`)
+    pub fn labels(mut self, labels: Vec<String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Merge three files (`-` is standard input, for at most one of them)
+    pub fn merge_files(&self, base: &str, ours: &str, theirs: &str) -> Result<DiffResult> {
+        if [base, ours, theirs].iter().filter(|path| input::is_stdin(path)).count() > 1 {
+            bail!("Standard input can only be read once");
+        }
+
+        let base_data = input::read_input(base)?;
+        let ours_data = input::read_input(ours)?;
+        let theirs_data = input::read_input(theirs)?;
+
+        self.merge_bytes(&base_data, &ours_data, &theirs_data, [base, ours, theirs])
+    }
+
+    /// Merge three contents already in memory; `paths` are the base, ours and
+    /// theirs paths.
+    ///
+    /// The result holds the diffs from the base to each side (the files
+    /// without changes are left out) and the merge itself.
+    pub fn merge_bytes(&self, base: &[u8], ours: &[u8], theirs: &[u8], paths: [&str; 3]) -> Result<DiffResult> {
+        let [base_path, ours_path, theirs_path] = paths;
+        let label = |index: usize, path: &str| self.labels.get(index).map_or(path, String::as_str).to_string();
+        let names = [label(1, base_path), label(0, ours_path), label(2, theirs_path)];
+
+        let mut texts = Vec::with_capacity(3);
+        for (bytes, name) in [base, ours, theirs].into_iter().zip(&names) {
+            if utils_fs::is_binary(bytes) {
+                bail!("Cannot merge binary file {}", name);
+            }
+            texts.push(self.file_differ.decode(bytes).with_context(|| format!("Cannot decode {}", name))?);
+        }
+        let [base_text, ours_text, theirs_text] = texts.as_slice() else {
+            unreachable!("three inputs are decoded");
+        };
+
+        let [base_name, ours_name, theirs_name] = &names;
+        let side_diff = |text, name: &String| FileDiff {
+            path: name.clone(),
+            ..self.file_differ.compare_decoded(base_text, text, base_name, name)
+        };
+        let ours_diff = side_diff(ours_text, ours_name);
+        let theirs_diff = side_diff(theirs_text, theirs_name);
+
+        let merge = self.merge_text(&base_text.text, &ours_text.text, &theirs_text.text, [base_name, ours_name, theirs_name]);
+
+        Ok(DiffResult {
+            merge: Some(merge),
+            ..DiffResult::from_files([ours_diff, theirs_diff])
+        })
+    }
+
+    /// Three-way merge of texts; `names` of the base, ours and theirs follow
+    /// the conflict markers
+    pub fn merge_text(&self, base: &str, ours: &str, theirs: &str, names: [&str; 3]) -> MergeResult {
+        let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+        let ours_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+        let theirs_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+
+        let ours_matches = self.match_lines(&base_lines, &ours_lines);
+        let theirs_matches = self.match_lines(&base_lines, &theirs_lines);
+        let regions = self.regions(&base_lines, &ours_lines, &theirs_lines, &ours_matches, &theirs_matches);
+
+        let (merged, conflicts) = self.write_merged(&regions, &base_lines, &ours_lines, &theirs_lines, names);
+        let chunks = regions
+            .iter()
+            .map(|region| MergeChunk {
+                kind: region.kind,
+                base: merge_lines(&base_lines, region.base.clone()),
+                ours: merge_lines(&ours_lines, region.ours.clone()),
+                theirs: merge_lines(&theirs_lines, region.theirs.clone()),
+            })
+            .collect();
+
+        let [base_path, ours_path, theirs_path] = names;
+        MergeResult {
+            base_path: base_path.to_string(),
+            ours_path: ours_path.to_string(),
+            theirs_path: theirs_path.to_string(),
+            conflict_style: self.conflict_style,
+            chunks,
+            conflicts,
+            merged,
+        }
+    }
+
+    /// For each base line, the index of the unchanged line it matches on the
+    /// other side
+    fn match_lines(&self, base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+        // 只需要对齐关系，不需要行内差异
+        let options = DiffOptions {
+            inline_mode: InlineMode::None,
+            ..self.options.clone()
+        };
+
+        let mut matches = vec![None; base.len()];
+        for line in diff_lines(base, other, 0, 0, &options) {
+            if let (ChangeType::Context, Some(base_num), Some(other_num)) = (&line.change_type, line.old_line_num, line.new_line_num) {
+                matches[base_num - 1] = Some(other_num - 1);
+            }
+        }
+        matches
+    }
+
+    /// Split the files into alternating stable regions, where a run of base
+    /// lines is unchanged on both sides, and unstable regions in between (the
+    /// classic diff3 algorithm)
+    fn regions(
+        &self,
+        base: &[&str],
+        ours: &[&str],
+        theirs: &[&str],
+        ours_matches: &[Option<usize>],
+        theirs_matches: &[Option<usize>],
+    ) -> Vec<Region> {
+        let mut regions = Vec::new();
+        let (mut i, mut j, mut k) = (0, 0, 0);
+
+        while i < base.len() || j < ours.len() || k < theirs.len() {
+            let start = (i, j, k);
+            while i < base.len() && ours_matches[i] == Some(j) && theirs_matches[i] == Some(k) {
+                i += 1;
+                j += 1;
+                k += 1;
+            }
+            if i > start.0 {
+                regions.push(Region {
+                    kind: MergeChunkKind::Unchanged,
+                    base: start.0..i,
+                    ours: start.1..j,
+                    theirs: start.2..k,
+                });
+                continue;
+            }
+
+            // 不稳定区域延伸到下一个在两侧都未改动的 base 行
+            let (base_end, ours_end, theirs_end) = (i..base.len())
+                .find_map(|o| Some((o, ours_matches[o]?, theirs_matches[o]?)))
+                .unwrap_or((base.len(), ours.len(), theirs.len()));
+
+            let base_range = i..base_end;
+            let ours_range = j..ours_end;
+            let theirs_range = k..theirs_end;
+            let kind = match (
+                self.same_lines(&base[base_range.clone()], &ours[ours_range.clone()]),
+                self.same_lines(&base[base_range.clone()], &theirs[theirs_range.clone()]),
+            ) {
+                (true, true) => MergeChunkKind::Unchanged,
+                (true, false) => MergeChunkKind::Theirs,
+                (false, true) => MergeChunkKind::Ours,
+                (false, false) if self.same_lines(&ours[ours_range.clone()], &theirs[theirs_range.clone()]) => MergeChunkKind::Both,
+                (false, false) => MergeChunkKind::Conflict,
+            };
+
+            regions.push(Region {
+                kind,
+                base: base_range,
+                ours: ours_range,
+                theirs: theirs_range,
+            });
+            (i, j, k) = (base_end, ours_end, theirs_end);
+        }
+
+        regions
+    }
+
+    /// Whether two runs of lines are equal under the comparison options
+    fn same_lines(&self, a: &[&str], b: &[&str]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| normalize_line(x, &self.options) == normalize_line(y, &self.options))
+    }
+
+    /// Write the merged text, taking each changed region from the side that
+    /// changed it and marking conflicts
+    fn write_merged(
+        &self,
+        regions: &[Region],
+        base: &[&str],
+        ours: &[&str],
+        theirs: &[&str],
+        names: [&str; 3],
+    ) -> (String, Vec<MergeConflict>) {
+        let [base_name, ours_name, theirs_name] = names;
+        // 冲突标记使用 ours 的换行符
+        let eol = if ours.first().is_some_and(|line| line.ends_with("\r\n")) { "\r\n" } else { "\n" };
+        let mut merged = MergedText { text: String::new(), line_count: 0, eol };
+
+        let mut conflicts = Vec::new();
+        for (index, region) in regions.iter().enumerate() {
+            match region.kind {
+                MergeChunkKind::Unchanged | MergeChunkKind::Ours | MergeChunkKind::Both => {
+                    merged.push_lines(&ours[region.ours.clone()], false);
+                }
+                MergeChunkKind::Theirs => merged.push_lines(&theirs[region.theirs.clone()], false),
+                MergeChunkKind::Conflict => {
+                    let mut ours_range = region.ours.clone();
+                    let mut theirs_range = region.theirs.clone();
+                    let mut suffix = 0;
+                    if self.conflict_style == ConflictStyle::Zdiff3 {
+                        // 两侧相同的开头和结尾不属于冲突
+                        let ours_lines = &ours[ours_range.clone()];
+                        let theirs_lines = &theirs[theirs_range.clone()];
+                        let same = |(a, b): &(&&str, &&str)| self.same_lines(&[a], &[b]);
+                        let prefix = ours_lines.iter().zip(theirs_lines).take_while(same).count();
+                        suffix = ours_lines[prefix..]
+                            .iter()
+                            .rev()
+                            .zip(theirs_lines[prefix..].iter().rev())
+                            .take_while(same)
+                            .count();
+
+                        merged.push_lines(&ours_lines[..prefix], false);
+                        ours_range = ours_range.start + prefix..ours_range.end - suffix;
+                        theirs_range = theirs_range.start + prefix..theirs_range.end - suffix;
+                    }
+
+                    let merged_line = merged.line_count + 1;
+                    merged.push_marker("<<<<<<<", Some(ours_name));
+                    merged.push_lines(&ours[ours_range.clone()], true);
+                    if self.conflict_style != ConflictStyle::Merge {
+                        merged.push_marker("|||||||", Some(base_name));
+                        merged.push_lines(&base[region.base.clone()], true);
+                    }
+                    merged.push_marker("=======", None);
+                    merged.push_lines(&theirs[theirs_range.clone()], true);
+                    merged.push_marker(">>>>>>>", Some(theirs_name));
+                    merged.push_lines(&ours[ours_range.end..ours_range.end + suffix], false);
+
+                    conflicts.push(MergeConflict {
+                        chunk: index,
+                        merged_line,
+                        base: merge_lines(base, region.base.clone()),
+                        ours: merge_lines(ours, ours_range),
+                        theirs: merge_lines(theirs, theirs_range),
+                    });
+                }
+            }
+        }
+
+        (merged.text, conflicts)
+    }
+}
+
+/// Merged text being written, counting its lines
+struct MergedText<'a> {
+    text: String,
+    line_count: usize,
+    eol: &'a str,
+}
+
+impl MergedText<'_> {
+    /// Append lines; `terminate` adds a missing final line terminator, which
+    /// inside a conflict would otherwise join the next marker
+    fn push_lines(&mut self, lines: &[&str], terminate: bool) {
+        for line in lines {
+            self.text.push_str(line);
+            if terminate && !line.ends_with('\n') {
+                self.text.push_str(self.eol);
+            }
+        }
+        self.line_count += lines.len();
+    }
+
+    fn push_marker(&mut self, marker: &str, name: Option<&str>) {
+        self.text.push_str(marker);
+        if let Some(name) = name {
+            self.text.push(' ');
+            self.text.push_str(name);
+        }
+        self.text.push_str(self.eol);
+        self.line_count += 1;
+    }
+}
+
+/// Lines of a range for display, without line terminators
+fn merge_lines(lines: &[&str], range: Range<usize>) -> MergeLines {
+    MergeLines {
+        start: range.start + 1,
+        lines: lines[range].iter().map(|line| line.trim_end_matches(['\n', '\r']).to_string()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 3] = ["base.txt", "ours.txt", "theirs.txt"];
+
+    fn merge(style: ConflictStyle, base: &str, ours: &str, theirs: &str) -> MergeResult {
+        Merger::new(DiffOptions::default())
+            .conflict_style(style)
+            .merge_text(base, ours, theirs, NAMES)
+    }
+
+    #[test]
+    fn test_clean_merge() {
+        let result = merge(ConflictStyle::Diff3, "a\nb\nc\nd\ne\n", "a\nB\nc\nd\ne\n", "a\nb\nc\nD\ne\nf\n");
+
+        assert_eq!(result.merged, "a\nB\nc\nD\ne\nf\n");
+        assert!(!result.has_conflicts());
+        let kinds: Vec<_> = result.chunks.iter().map(|chunk| chunk.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                MergeChunkKind::Unchanged,
+                MergeChunkKind::Ours,
+                MergeChunkKind::Unchanged,
+                MergeChunkKind::Theirs,
+                MergeChunkKind::Unchanged,
+                MergeChunkKind::Theirs,
+            ]
+        );
+        assert_eq!(result.chunks[3].theirs, MergeLines { start: 4, lines: vec!["D".to_string()] });
+
+        // 两侧相同的修改不算冲突
+        let result = merge(ConflictStyle::Diff3, "a\nb\nc\n", "a\nx\nc\n", "a\nx\nc\n");
+        assert_eq!(result.merged, "a\nx\nc\n");
+        assert_eq!(result.chunks[1].kind, MergeChunkKind::Both);
+    }
+
+    #[test]
+    fn test_conflict_styles() {
+        let (base, ours, theirs) = ("a\nb\nc\n", "a\n1\nX\n2\nc\n", "a\n1\nY\n2\nc\n");
+
+        let result = merge(ConflictStyle::Diff3, base, ours, theirs);
+        assert_eq!(
+            result.merged,
+            "a\n<<<<<<< ours.txt\n1\nX\n2\n||||||| base.txt\nb\n=======\n1\nY\n2\n>>>>>>> theirs.txt\nc\n"
+        );
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(result.conflicts[0].chunk, 1);
+        assert_eq!(result.conflicts[0].merged_line, 2);
+        assert_eq!(result.conflicts[0].base.lines, vec!["b"]);
+
+        let result = merge(ConflictStyle::Merge, base, ours, theirs);
+        assert_eq!(result.merged, "a\n<<<<<<< ours.txt\n1\nX\n2\n=======\n1\nY\n2\n>>>>>>> theirs.txt\nc\n");
+
+        let result = merge(ConflictStyle::Zdiff3, base, ours, theirs);
+        assert_eq!(
+            result.merged,
+            "a\n1\n<<<<<<< ours.txt\nX\n||||||| base.txt\nb\n=======\nY\n>>>>>>> theirs.txt\n2\nc\n"
+        );
+        assert_eq!(result.conflicts[0].merged_line, 3);
+        assert_eq!(result.conflicts[0].ours, MergeLines { start: 3, lines: vec!["X".to_string()] });
+    }
+
+    #[test]
+    fn test_conflict_without_final_newline() {
+        let result = merge(ConflictStyle::Merge, "a\nb", "a\nx", "a\ny");
+        assert_eq!(result.merged, "a\n<<<<<<< ours.txt\nx\n=======\ny\n>>>>>>> theirs.txt\n");
+
+        let result = merge(ConflictStyle::Merge, "a\r\nb\r\n", "a\r\nx\r\n", "a\r\ny\r\n");
+        assert_eq!(result.merged, "a\r\n<<<<<<< ours.txt\r\nx\r\n=======\r\ny\r\n>>>>>>> theirs.txt\r\n");
+    }
+
+    #[test]
+    fn test_merge_bytes() {
+        let merger = Merger::new(DiffOptions::default()).labels(vec!["mine".to_string()]);
+        let result = merger.merge_bytes(b"a\nb\n", b"a\nb\nc\n", b"a\nb\n", NAMES).unwrap();
+
+        // theirs 没有改动，只有 base -> ours 的 diff
+        assert_eq!(result.files.len(), 1);
+        assert_eq!(result.files[0].path, "mine");
+        assert_eq!(result.files[0].old_path, "base.txt");
+        let merge = result.merge.unwrap();
+        assert_eq!(merge.ours_path, "mine");
+        assert_eq!(merge.merged, "a\nb\nc\n");
+
+        assert!(merger.merge_bytes(b"a\n", b"\0\x01", b"a\n", NAMES).is_err());
+    }
+}
@@ -1,5 +1,18 @@
 pub mod types;
+pub mod binary;
+pub mod histogram;
+pub mod hunk;
+pub mod inline;
+pub mod json;
+pub mod line_ending;
+pub mod normalize;
+pub mod rename;
 pub mod file;
 pub mod directory;
+pub mod git;
+pub mod merge;
+pub mod patch;
+pub mod apply;
 pub mod formatter;
+pub mod side_by_side;
 pub mod large_file;
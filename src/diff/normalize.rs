@@ -0,0 +1,101 @@
+use std::borrow::Cow;
+
+use super::types::DiffOptions;
+
+/// Build the comparison key for a line according to the diff options.
+///
+/// The key is only used to decide whether two lines are equal; the original
+/// line is still what gets displayed. A trailing `\n` is kept in the key so a
+/// missing final newline is still reported as a change, unless
+/// `complete_last_line` is set.
+pub fn normalize_line<'a>(line: &'a str, options: &DiffOptions) -> Cow<'a, str> {
+    if !options.needs_normalization() {
+        return Cow::Borrowed(line);
+    }
+
+    let (mut content, eol) = match line.strip_suffix('\n') {
+        Some(content) => (content, "\n"),
+        None if options.complete_last_line => (line, "\n"),
+        None => (line, ""),
+    };
+
+    if options.strip_trailing_cr {
+        content = content.strip_suffix('\r').unwrap_or(content);
+    }
+
+    let mut key: String = if options.ignore_whitespace {
+        // -w: 忽略所有空白字符
+        content.chars().filter(|c| !c.is_whitespace()).collect()
+    } else if options.ignore_space_change {
+        // -b: 连续空白视为一个空格，忽略行尾空白
+        let mut collapsed = String::with_capacity(content.len());
+        let mut in_space = false;
+        for c in content.trim_end().chars() {
+            if c.is_whitespace() {
+                if !in_space {
+                    collapsed.push(' ');
+                }
+                in_space = true;
+            } else {
+                collapsed.push(c);
+                in_space = false;
+            }
+        }
+        collapsed
+    } else {
+        content.to_string()
+    };
+
+    if options.ignore_case {
+        key = key.to_lowercase();
+    }
+
+    key.push_str(eol);
+    Cow::Owned(key)
+}
+
+/// Check whether a line is blank, for `--ignore-blank-lines`.
+///
+/// Like GNU diff, only empty lines are blank; a line of spaces is blank only
+/// when `-b` or `-w` normalizes it to an empty line.
+pub fn is_blank_line(line: &str, options: &DiffOptions) -> bool {
+    normalize_line(line, options).trim_end_matches('\n').is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_all_space() {
+        let options = DiffOptions {
+            ignore_whitespace: true,
+            ..Default::default()
+        };
+
+        assert_eq!(normalize_line("  a b\tc \n", &options), "abc\n");
+        assert_eq!(normalize_line("abc", &options), "abc");
+    }
+
+    #[test]
+    fn test_ignore_space_change() {
+        let options = DiffOptions {
+            ignore_space_change: true,
+            ..Default::default()
+        };
+
+        assert_eq!(normalize_line("a  b \t\n", &options), normalize_line("a b\n", &options));
+        assert_ne!(normalize_line(" a\n", &options), normalize_line("a\n", &options));
+    }
+
+    #[test]
+    fn test_ignore_case_and_trailing_cr() {
+        let options = DiffOptions {
+            ignore_case: true,
+            strip_trailing_cr: true,
+            ..Default::default()
+        };
+
+        assert_eq!(normalize_line("Hello\r\n", &options), normalize_line("hello\n", &options));
+    }
+}
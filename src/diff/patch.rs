@@ -0,0 +1,550 @@
+use anyhow::{Context, Result, bail};
+
+use super::inline::inline_diff;
+use super::types::*;
+
+/// Parse a unified diff, as written by `diff -u`, `git diff` or rdiff, into a
+/// `DiffResult`.
+///
+/// `strip` leading path components are removed from the names in the
+/// `diff --git`, `---` and `+++` lines, like `patch -p`; `1` removes the
+/// `a/` and `b/` prefixes. Text that is not part of a file diff, such as a
+/// commit message or a summary line, is skipped.
+pub fn parse_patch(text: &str, strip: usize) -> Result<DiffResult> {
+    // 只按 `\n` 切分：CRLF 文件的补丁中，行尾的 `\r` 属于 hunk 行的内容
+    let lines: Vec<&str> = text.split_terminator('\n').collect();
+    let mut files = Vec::new();
+    let mut current: Option<FileDiff> = None;
+    let mut index = 0;
+
+    while index < lines.len() {
+        // 文件头和扩展头不含 `\r`，整个补丁被转换成 CRLF 时也能识别
+        let line = lines[index].strip_suffix('\r').unwrap_or(lines[index]);
+        let line_num = index + 1;
+        index += 1;
+
+        if let Some(names) = line.strip_prefix("diff --git ") {
+            files.extend(current.take());
+            let (old_name, new_name) =
+                split_git_names(names).with_context(|| format!("Malformed diff header at line {}", line_num))?;
+            current = Some(empty_file_diff(&strip_path(&old_name, strip), &strip_path(&new_name, strip)));
+            continue;
+        }
+
+        if line.starts_with("--- ") && lines.get(index).is_some_and(|next| next.starts_with("+++ ")) {
+            let old_name = header_name(&line[4..]);
+            let new_name = header_name(&lines[index][4..]);
+            index += 1;
+
+            // 没有 diff --git 头的普通 diff，或者 git diff 中第一次出现 ---/+++
+            let file_diff = match current.take() {
+                Some(file_diff) if file_diff.hunks.is_empty() => file_diff,
+                previous => {
+                    files.extend(previous);
+                    empty_file_diff(&old_name, &new_name)
+                }
+            };
+            current = Some(with_names(file_diff, &strip_path(&old_name, strip), &strip_path(&new_name, strip)));
+            continue;
+        }
+
+        let Some(file_diff) = current.as_mut() else {
+            continue;
+        };
+
+        if line.starts_with("@@ ") {
+            let (hunk, consumed, missing) = parse_hunk(&lines[index - 1..], line_num)?;
+            index += consumed - 1;
+            if missing.old.is_some() || missing.new.is_some() {
+                let recorded = file_diff.missing_newline.get_or_insert(MissingNewline { old: None, new: None });
+                recorded.old = missing.old.or(recorded.old);
+                recorded.new = missing.new.or(recorded.new);
+            }
+            file_diff.hunks.push(hunk);
+        } else if let Some(mode) = line.strip_prefix("new file mode ") {
+            file_diff.is_new = true;
+            file_diff.status = FileStatus::Added;
+            file_diff.old_path = "/dev/null".to_string();
+            modes(file_diff).new = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+            file_diff.is_deleted = true;
+            file_diff.status = FileStatus::Deleted;
+            file_diff.new_path = "/dev/null".to_string();
+            modes(file_diff).old = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("old mode ") {
+            modes(file_diff).old = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("new mode ") {
+            modes(file_diff).new = Some(mode.to_string());
+        } else if let Some(path) = line.strip_prefix("rename from ").or_else(|| line.strip_prefix("copy from ")) {
+            file_diff.old_path = unquote(path);
+        } else if let Some(path) = line.strip_prefix("rename to ").or_else(|| line.strip_prefix("copy to ")) {
+            file_diff.new_path = unquote(path);
+            file_diff.path = file_diff.new_path.clone();
+            file_diff.status = if line.starts_with("rename") { FileStatus::Renamed } else { FileStatus::Copied };
+        } else if let Some(percent) = line.strip_prefix("similarity index ") {
+            file_diff.similarity = percent.trim_end_matches('%').parse().ok();
+        } else if line.starts_with("Binary files ") && line.ends_with(" differ") {
+            file_diff.is_binary = true;
+        } else if line == "GIT binary patch" {
+            // 跳过 base85 编码的 literal/delta 数据，直到下一个文件
+            file_diff.is_binary = true;
+            while lines.get(index).is_some_and(|line| !line.starts_with("diff --git ")) {
+                index += 1;
+            }
+        }
+    }
+    files.extend(current);
+
+    Ok(DiffResult::from_files(files))
+}
+
+/// Turn the deleted lines directly followed by added lines into modified
+/// lines with intra-line highlighting, as `diff_lines` does for compared files.
+///
+/// A patch only records deletions and additions, so without this step the web
+/// viewer could not show which words of a changed line differ.
+pub fn pair_modified_lines(file_diff: &mut FileDiff, mode: InlineMode) {
+    for hunk in &mut file_diff.hunks {
+        let lines = std::mem::take(&mut hunk.lines);
+        let mut paired = Vec::with_capacity(lines.len());
+        let mut index = 0;
+
+        while index < lines.len() {
+            let deletes = lines[index..].iter().take_while(|line| matches!(line.change_type, ChangeType::Delete)).count();
+            let adds = lines[index + deletes..]
+                .iter()
+                .take_while(|line| matches!(line.change_type, ChangeType::Add))
+                .count();
+            if deletes == 0 || adds == 0 {
+                let end = index + deletes.max(1);
+                paired.extend_from_slice(&lines[index..end]);
+                index = end;
+                continue;
+            }
+
+            // 按顺序配对，与 diff_lines 的 Replace 分支一致
+            let (old_run, new_run) = lines[index..index + deletes + adds].split_at(deletes);
+            for (old, new) in old_run.iter().zip(new_run) {
+                match inline_diff(&old.content, &new.content, mode) {
+                    Some(inline) => paired.push(LineChange {
+                        change_type: ChangeType::Modify,
+                        old_line_num: old.old_line_num,
+                        new_line_num: new.new_line_num,
+                        content: new.content.clone(),
+                        old_content: Some(old.content.clone()),
+                        inline: Some(inline),
+                    }),
+                    None => paired.extend([old.clone(), new.clone()]),
+                }
+            }
+            let common = deletes.min(adds);
+            paired.extend_from_slice(&old_run[common..]);
+            paired.extend_from_slice(&new_run[common..]);
+            index += deletes + adds;
+        }
+
+        hunk.lines = paired;
+    }
+}
+
+/// Parse a hunk starting at its `@@` line; returns the hunk, the number of
+/// lines it spans and the lines followed by `\ No newline at end of file`
+fn parse_hunk(lines: &[&str], line_num: usize) -> Result<(Hunk, usize, MissingNewline)> {
+    let (old_start, old_count, new_start, new_count) =
+        parse_hunk_header(lines[0]).with_context(|| format!("Malformed hunk header at line {}", line_num))?;
+
+    // 行数为 0 时起始行号是前一行
+    let mut old_num = if old_count == 0 { old_start + 1 } else { old_start };
+    let mut new_num = if new_count == 0 { new_start + 1 } else { new_start };
+    let (mut old_left, mut new_left) = (old_count, new_count);
+    let mut hunk_lines = Vec::new();
+    let mut missing = MissingNewline { old: None, new: None };
+    let mut consumed = 1;
+
+    while old_left > 0 || new_left > 0 || lines.get(consumed).is_some_and(|line| line.starts_with('\\')) {
+        let Some(&line) = lines.get(consumed) else {
+            bail!("Hunk at line {} ends before all of its lines", line_num);
+        };
+        consumed += 1;
+
+        // 有些编辑器会删除空上下文行末尾的空格
+        let mut chars = line.chars();
+        let (marker, content) = match chars.next() {
+            Some(marker) if line != "\r" => (marker, chars.as_str().to_string()),
+            _ => (' ', line.to_string()),
+        };
+        let line_change = |change_type, old_line_num, new_line_num| LineChange {
+            change_type,
+            old_line_num,
+            new_line_num,
+            content,
+            old_content: None,
+            inline: None,
+        };
+
+        match marker {
+            ' ' if old_left > 0 && new_left > 0 => {
+                hunk_lines.push(line_change(ChangeType::Context, Some(old_num), Some(new_num)));
+                old_num += 1;
+                new_num += 1;
+                old_left -= 1;
+                new_left -= 1;
+            }
+            '-' if old_left > 0 => {
+                hunk_lines.push(line_change(ChangeType::Delete, Some(old_num), None));
+                old_num += 1;
+                old_left -= 1;
+            }
+            '+' if new_left > 0 => {
+                hunk_lines.push(line_change(ChangeType::Add, None, Some(new_num)));
+                new_num += 1;
+                new_left -= 1;
+            }
+            '\\' => {
+                // `\ No newline at end of file` 修饰前一行
+                let previous = hunk_lines.last().with_context(|| format!("Unexpected line at line {}", line_num + consumed - 1))?;
+                if !matches!(previous.change_type, ChangeType::Add) {
+                    missing.old = previous.old_line_num;
+                }
+                if !matches!(previous.change_type, ChangeType::Delete) {
+                    missing.new = previous.new_line_num;
+                }
+            }
+            _ => bail!("Hunk at line {} does not match its header (line {})", line_num, line_num + consumed - 1),
+        }
+    }
+
+    let hunk = Hunk {
+        old_start,
+        old_count,
+        new_start,
+        new_count,
+        lines: hunk_lines,
+    };
+    Ok((hunk, consumed, missing))
+}
+
+/// Parse `@@ -OLD[,COUNT] +NEW[,COUNT] @@`; a missing count is 1
+fn parse_hunk_header(line: &str) -> Option<(usize, usize, usize, usize)> {
+    let ranges = line.strip_prefix("@@ -")?;
+    let (ranges, _) = ranges.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(old)?;
+    let (new_start, new_count) = range(new)?;
+    Some((old_start, old_count, new_start, new_count))
+}
+
+/// Split the names of a `diff --git a/x b/y` line
+fn split_git_names(names: &str) -> Option<(String, String)> {
+    if names.starts_with('"') {
+        let end = quoted_end(names)?;
+        let new_name = names[end..].trim_start();
+        return Some((unquote(&names[..end]), unquote(new_name)));
+    }
+
+    // 路径可能包含空格：优先按两个相同路径（去掉前缀后）拆分
+    let half = names.len() / 2;
+    if names.len() % 2 == 1 && names.as_bytes()[half] == b' ' {
+        let (old, new) = (&names[..half], &names[half + 1..]);
+        if old.split_once('/').map(|(_, rest)| rest) == new.split_once('/').map(|(_, rest)| rest) {
+            return Some((old.to_string(), new.to_string()));
+        }
+    }
+    let (old, new) = names.split_once(" b/").map(|(old, new)| (old, format!("b/{}", new)))?;
+    Some((old.to_string(), new))
+}
+
+/// Byte index just past the closing quote of a C-quoted name
+fn quoted_end(text: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(i + 1),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Name in a `---`/`+++` line, without the timestamp `diff -u` appends
+fn header_name(text: &str) -> String {
+    let name = if text.starts_with('"') {
+        &text[..quoted_end(text).unwrap_or(text.len())]
+    } else {
+        text.split('\t').next().unwrap_or(text)
+    };
+    unquote(name.trim_end())
+}
+
+/// Undo git's C-style quoting of names with special characters
+fn unquote(name: &str) -> String {
+    let Some(inner) = name.strip_prefix('"').and_then(|name| name.strip_suffix('"')) else {
+        return name.to_string();
+    };
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.bytes().peekable();
+    while let Some(byte) = chars.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match chars.next() {
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(digit @ b'0'..=b'7') => {
+                // 八进制转义的 UTF-8 字节，如 \303\251
+                let mut value = (digit - b'0') as u32;
+                for _ in 0..2 {
+                    if let Some(&next @ b'0'..=b'7') = chars.peek() {
+                        value = value * 8 + (next - b'0') as u32;
+                        chars.next();
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            Some(other) => bytes.push(other),
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Remove `strip` leading components of a path, like `patch -p`
+fn strip_path(path: &str, strip: usize) -> String {
+    if path == "/dev/null" {
+        return path.to_string();
+    }
+    let mut rest = path;
+    for _ in 0..strip {
+        match rest.split_once('/') {
+            Some((_, tail)) => rest = tail.trim_start_matches('/'),
+            None => break,
+        }
+    }
+    rest.to_string()
+}
+
+/// File modes of a file diff, recorded on first use
+fn modes(file_diff: &mut FileDiff) -> &mut FileModes {
+    file_diff.modes.get_or_insert(FileModes { old: None, new: None })
+}
+
+/// Set the names from the `---`/`+++` lines; `/dev/null` marks an added or
+/// deleted file
+fn with_names(file_diff: FileDiff, old_name: &str, new_name: &str) -> FileDiff {
+    let is_new = file_diff.is_new || old_name == "/dev/null";
+    let is_deleted = file_diff.is_deleted || new_name == "/dev/null";
+    let status = match file_diff.status {
+        FileStatus::Modified if is_new => FileStatus::Added,
+        FileStatus::Modified if is_deleted => FileStatus::Deleted,
+        status => status,
+    };
+    let renamed = matches!(status, FileStatus::Renamed | FileStatus::Copied);
+
+    FileDiff {
+        path: if is_deleted { old_name.to_string() } else { new_name.to_string() },
+        // 重命名的路径以 rename from/to 为准
+        old_path: if renamed { file_diff.old_path.clone() } else { old_name.to_string() },
+        new_path: if renamed { file_diff.new_path.clone() } else { new_name.to_string() },
+        is_new,
+        is_deleted,
+        status,
+        ..file_diff
+    }
+}
+
+/// File diff with names only; hunks and headers are filled in while parsing
+fn empty_file_diff(old_name: &str, new_name: &str) -> FileDiff {
+    FileDiff {
+        path: new_name.to_string(),
+        old_path: old_name.to_string(),
+        new_path: new_name.to_string(),
+        is_binary: false,
+        is_new: false,
+        is_deleted: false,
+        hunks: vec![],
+        full_content: None,
+        binary_diff: None,
+        encodings: None,
+        line_endings: None,
+        missing_newline: None,
+        modes: None,
+        mtimes: None,
+        status: FileStatus::Modified,
+        similarity: None,
+        error: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_git_patch() {
+        let patch = "\
+commit message, ignored
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@ fn main() {
+ a
+-b
++B
+ c
+@@ -10 +10,2 @@
+ j
++k
+\\ No newline at end of file
+diff --git a/new.txt b/new.txt
+new file mode 100644
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++hello
+diff --git a/empty b/empty
+deleted file mode 100644
+diff --git a/old name.txt b/new name.txt
+similarity index 90%
+rename from old name.txt
+rename to new name.txt
+
+3 file(s) changed
+";
+        let result = parse_patch(patch, 1).unwrap();
+        let summary: Vec<_> = result
+            .files
+            .iter()
+            .map(|f| (f.old_path.as_str(), f.new_path.as_str(), f.status, f.hunks.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("src/lib.rs", "src/lib.rs", FileStatus::Modified, 2),
+                ("/dev/null", "new.txt", FileStatus::Added, 1),
+                ("empty", "/dev/null", FileStatus::Deleted, 0),
+                ("old name.txt", "new name.txt", FileStatus::Renamed, 0),
+            ]
+        );
+
+        let lib = &result.files[0];
+        assert_eq!(lib.count_changes(), (2, 1));
+        assert_eq!((lib.hunks[1].old_count, lib.hunks[1].new_count), (1, 2));
+        assert_eq!(lib.hunks[1].lines[1].new_line_num, Some(11));
+        assert_eq!(lib.missing_newline, Some(MissingNewline { old: None, new: Some(11) }));
+        assert_eq!(result.files[3].similarity, Some(90));
+        assert_eq!(result.total_additions, 3);
+    }
+
+    #[test]
+    fn test_parse_plain_patch() {
+        let patch = "\
+diff -u old/f.txt new/f.txt
+--- old/f.txt\t2024-01-01 00:00:00.000000000 +0000
++++ new/f.txt\t2024-01-02 00:00:00.000000000 +0000
+@@ -1,2 +1,2 @@
+-x
+
++y
+--- /dev/null
++++ new/g.txt
+@@ -0,0 +1,2 @@
++1
++2
+";
+        let result = parse_patch(patch, 0).unwrap();
+        assert_eq!(result.files.len(), 2);
+        assert_eq!(result.files[0].old_path, "old/f.txt");
+        // 去掉了空格的空上下文行
+        assert!(matches!(result.files[0].hunks[0].lines[1].change_type, ChangeType::Context));
+        assert_eq!(result.files[1].path, "new/g.txt");
+        assert!(result.files[1].is_new);
+
+        assert_eq!(parse_patch(patch, 1).unwrap().files[0].path, "f.txt");
+        assert!(parse_patch("--- a\n+++ b\n@@ -1,2 +1,2 @@\n-x\n", 1).is_err());
+        assert!(parse_patch("--- a\n+++ b\n@@ -1,x +1 @@\n", 1).is_err());
+        assert!(parse_patch("--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n-a\né\n", 1).is_err());
+    }
+
+    #[test]
+    fn test_keep_carriage_returns() {
+        // CRLF 文件的 hunk 行保留 `\r`；整个补丁是 CRLF 时文件头照样识别
+        let patch = "diff --git a/f b/f\r\n--- a/f\r\n+++ b/f\r\n@@ -1,3 +1,3 @@\r\n a\r\n-b\r\n+c\r\n\r\n";
+        let file = parse_patch(patch, 1).unwrap().files.remove(0);
+        assert_eq!(file.path, "f");
+        let contents: Vec<_> = file.hunks[0].lines.iter().map(|line| line.content.as_str()).collect();
+        assert_eq!(contents, ["a\r", "b\r", "c\r", "\r"]);
+    }
+
+    #[test]
+    fn test_parse_extended_headers() {
+        let patch = "\
+diff --git a/run.sh b/run.sh
+old mode 100644
+new mode 100755
+diff --git a/tool b/tool
+new file mode 100755
+index 0000000..1111111
+GIT binary patch
+literal 2
+JcmZQzW&i>J00961
+
+literal 0
+HcmV?d00001
+
+diff --git a/f.txt b/f.txt
+deleted file mode 100644
+--- a/f.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+";
+        let result = parse_patch(patch, 1).unwrap();
+        assert_eq!(result.files.len(), 3);
+        assert!(result.files[0].mode_changed());
+        assert!(result.files[0].has_changes());
+        assert!(result.files[1].is_binary && result.files[1].is_new);
+        assert_eq!(result.files[1].modes.as_ref().unwrap().new.as_deref(), Some("100755"));
+        assert_eq!(result.files[2].modes.as_ref().unwrap().old.as_deref(), Some("100644"));
+        assert!(result.files[2].is_deleted);
+    }
+
+    #[test]
+    fn test_pair_modified_lines() {
+        let patch = "--- a/f\n+++ b/f\n@@ -1,4 +1,3 @@\n-let x = 1;\n-old\n+let x = 2;\n c\n-gone\n+completely different\n";
+        let mut result = parse_patch(patch, 1).unwrap();
+        pair_modified_lines(&mut result.files[0], InlineMode::Word);
+
+        let kinds: Vec<_> = result.files[0].hunks[0].lines.iter().map(|line| &line.change_type).collect();
+        assert!(matches!(
+            kinds.as_slice(),
+            [ChangeType::Modify, ChangeType::Delete, ChangeType::Context, ChangeType::Delete, ChangeType::Add]
+        ));
+        let modified = &result.files[0].hunks[0].lines[0];
+        assert_eq!(modified.old_content.as_deref(), Some("let x = 1;"));
+        assert_eq!((modified.old_line_num, modified.new_line_num), (Some(1), Some(1)));
+        assert_eq!(result.files[0].count_changes(), (2, 3));
+    }
+
+    #[test]
+    fn test_quoted_names() {
+        assert_eq!(unquote(r#""caf\303\251 \"x\".txt""#), "café \"x\".txt");
+        assert_eq!(
+            split_git_names(r#""a/caf\303\251" "b/caf\303\251""#),
+            Some(("a/café".to_string(), "b/café".to_string()))
+        );
+        assert_eq!(split_git_names("a/x y b/x y"), Some(("a/x y".to_string(), "b/x y".to_string())));
+        assert_eq!(strip_path("a/b/c", 1), "b/c");
+        assert_eq!(strip_path("/dev/null", 1), "/dev/null");
+    }
+}
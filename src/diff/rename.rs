@@ -0,0 +1,149 @@
+use rayon::prelude::*;
+use similar::TextDiff;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::utils::encoding::DecodedText;
+
+/// Above this many deleted × added pairs only exact renames are detected
+const MAX_INEXACT_PAIRS: usize = 1000 * 1000;
+
+/// Time limit for scoring a single pair of files
+const SIMILARITY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A file that may be one side of a rename or copy
+pub struct Candidate {
+    pub rel_path: String,
+    pub bytes: Vec<u8>,
+    pub text: Option<DecodedText>, // None for binary files, which only match exactly
+}
+
+/// Similarity of two files in percent; only identical files score 100.
+///
+/// Text files are scored like `similar`'s line ratio: twice the number of
+/// matching lines divided by the total number of lines.
+pub fn similarity(old: &Candidate, new: &Candidate, threshold: u8) -> u8 {
+    if old.bytes == new.bytes {
+        return 100;
+    }
+
+    let (Some(old_text), Some(new_text)) = (&old.text, &new.text) else {
+        return 0;
+    };
+
+    // 行数相差太大时不可能达到阈值，跳过昂贵的 diff
+    let (old_text, new_text) = (old_text.text.as_str(), new_text.text.as_str());
+    let old_lines = old_text.lines().count();
+    let new_lines = new_text.lines().count();
+    let upper_bound = 200 * old_lines.min(new_lines) / (old_lines + new_lines).max(1);
+    if upper_bound < threshold as usize {
+        return 0;
+    }
+
+    let ratio = TextDiff::configure()
+        .timeout(SIMILARITY_TIMEOUT)
+        .diff_lines(old_text, new_text)
+        .ratio();
+
+    ((ratio * 100.0) as u8).min(99)
+}
+
+/// Pair deleted files with added files, best matches first; every file is
+/// used at most once. Returns `(deleted index, added index, similarity)`.
+pub fn match_renames(deleted: &[Candidate], added: &[Candidate], threshold: u8) -> Vec<(usize, usize, u8)> {
+    let exact_only = deleted.len() * added.len() > MAX_INEXACT_PAIRS;
+    let mut pairs = score_pairs(deleted, added, threshold, exact_only);
+
+    let mut deleted_used = vec![false; deleted.len()];
+    let mut added_used = vec![false; added.len()];
+    pairs.retain(|&(i, j, _)| {
+        if deleted_used[i] || added_used[j] {
+            return false;
+        }
+        deleted_used[i] = true;
+        added_used[j] = true;
+        true
+    });
+
+    pairs
+}
+
+/// For each added file, pick the most similar source file.
+/// Returns `(source index, added index, similarity)`.
+pub fn match_copies(sources: &[Candidate], added: &[Candidate], threshold: u8) -> Vec<(usize, usize, u8)> {
+    let exact_only = sources.len() * added.len() > MAX_INEXACT_PAIRS;
+    let mut pairs = score_pairs(sources, added, threshold, exact_only);
+
+    let mut added_used = vec![false; added.len()];
+    pairs.retain(|&(_, j, _)| !std::mem::replace(&mut added_used[j], true));
+
+    pairs
+}
+
+/// Score all pairs at or above the threshold, sorted best first.
+///
+/// Ties prefer files with the same name, then path order, so the result does
+/// not depend on scheduling.
+fn score_pairs(old: &[Candidate], new: &[Candidate], threshold: u8, exact_only: bool) -> Vec<(usize, usize, u8)> {
+    let mut pairs: Vec<(usize, usize, u8)> = (0..new.len())
+        .into_par_iter()
+        .flat_map_iter(|j| {
+            (0..old.len()).filter_map(move |i| {
+                let score = if exact_only {
+                    if old[i].bytes == new[j].bytes { 100 } else { 0 }
+                } else {
+                    similarity(&old[i], &new[j], threshold)
+                };
+                (score >= threshold).then_some((i, j, score))
+            })
+        })
+        .collect();
+
+    let same_name = |i: usize, j: usize| Path::new(&old[i].rel_path).file_name() == Path::new(&new[j].rel_path).file_name();
+    pairs.sort_by_key(|&(i, j, score)| (std::cmp::Reverse(score), !same_name(i, j), i, j));
+
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(rel_path: &str, text: &str) -> Candidate {
+        Candidate {
+            rel_path: rel_path.to_string(),
+            bytes: text.as_bytes().to_vec(),
+            text: Some(DecodedText {
+                text: text.to_string(),
+                encoding: encoding_rs::UTF_8,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_similarity() {
+        let old = candidate("a.rs", "1\n2\n3\n4\n");
+
+        assert_eq!(similarity(&old, &candidate("b.rs", "1\n2\n3\n4\n"), 50), 100);
+        assert_eq!(similarity(&old, &candidate("b.rs", "1\n2\n3\n5\n"), 50), 75);
+        assert_eq!(similarity(&old, &candidate("b.rs", "x\n"), 50), 0);
+    }
+
+    #[test]
+    fn test_match_renames_prefers_best_and_same_name() {
+        let deleted = vec![candidate("old/util.rs", "a\nb\nc\nd\n"), candidate("old/lib.rs", "w\nx\ny\nz\n")];
+        let added = vec![
+            candidate("new/lib.rs", "w\nx\ny\nchanged\n"),
+            candidate("new/util.rs", "a\nb\nc\nd\n"),
+            candidate("new/other.rs", "a\nb\nc\nd\n"),
+        ];
+
+        let pairs = match_renames(&deleted, &added, 50);
+
+        // util.rs 与两个新文件完全相同，同名的优先；每个旧文件只使用一次
+        assert_eq!(pairs, vec![(0, 1, 100), (1, 0, 75)]);
+
+        let copies = match_copies(&deleted, &added, 50);
+        assert_eq!(copies, vec![(0, 1, 100), (0, 2, 100), (1, 0, 75)]);
+    }
+}
@@ -0,0 +1,446 @@
+use unicode_width::UnicodeWidthChar;
+
+use super::formatter::{DiffFormatter, patch_path};
+use super::types::*;
+
+/// Output width when it is neither given nor detected, as in GNU diff
+pub const DEFAULT_WIDTH: usize = 130;
+
+/// Narrowest output that still leaves room for line numbers and some text
+const MIN_WIDTH: usize = 20;
+
+const TAB_WIDTH: usize = 8;
+
+/// How the two sides of a row relate, shown in the gutter like GNU sdiff
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Marker {
+    Common,   // 两侧相同
+    Changed,  // |
+    Deleted,  // <
+    Added,    // >
+}
+
+/// One side of a row
+struct Cell<'a> {
+    line_num: Option<usize>,
+    content: &'a str,
+    segments: Option<&'a [InlineSegment]>,
+}
+
+struct Row<'a> {
+    marker: Marker,
+    old: Option<Cell<'a>>,
+    new: Option<Cell<'a>>,
+}
+
+/// Part of a cell that fits on one terminal line
+#[derive(Debug, Default, PartialEq)]
+struct Piece {
+    spans: Vec<(String, bool)>, // 文本及是否为行内差异
+    width: usize,               // 显示宽度（列数）
+}
+
+/// Column widths of one file
+struct Layout {
+    half: usize,        // 每一栏的宽度
+    num_width: usize,   // 行号宽度
+    text_width: usize,  // 行号之后的文本宽度
+}
+
+/// Formats diffs in two columns, old file on the left and new file on the
+/// right, like `diff -y` and `sdiff`.
+///
+/// Every column starts with the line number. Long lines are truncated unless
+/// `wrap` is set, and tabs are expanded so the columns stay aligned; wide
+/// characters such as CJK count as two columns.
+pub struct SideBySideFormatter {
+    formatter: DiffFormatter,
+    use_color: bool,
+    width: usize,
+    left_column: bool,
+    suppress_common_lines: bool,
+    wrap: bool,
+}
+
+impl SideBySideFormatter {
+    pub fn new(use_color: bool) -> Self {
+        Self {
+            formatter: DiffFormatter::new(use_color),
+            use_color,
+            width: DEFAULT_WIDTH,
+            left_column: false,
+            suppress_common_lines: false,
+            wrap: false,
+        }
+    }
+
+    /// Total output width in columns, gutter included
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width.max(MIN_WIDTH);
+        self
+    }
+
+    /// Print common lines only in the left column
+    pub fn left_column(mut self, left_column: bool) -> Self {
+        self.left_column = left_column;
+        self
+    }
+
+    /// Do not print common lines
+    pub fn suppress_common_lines(mut self, suppress_common_lines: bool) -> Self {
+        self.suppress_common_lines = suppress_common_lines;
+        self
+    }
+
+    /// Wrap long lines onto continuation lines instead of truncating them
+    pub fn wrap(mut self, wrap: bool) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// Format every file of a diff result
+    pub fn format(&self, diff_result: &DiffResult) -> String {
+        let mut output = String::new();
+
+        for file_diff in diff_result.files.iter().filter(|file| !file.has_error()) {
+            output.push_str(&self.format_file_diff(file_diff));
+            output.push('\n');
+        }
+
+        output
+    }
+
+    /// Format a single file diff; files without text hunks (binary files,
+    /// pure renames) are printed like the unified output
+    pub fn format_file_diff(&self, file_diff: &FileDiff) -> String {
+        if file_diff.is_binary || file_diff.hunks.is_empty() {
+            return self.formatter.format_file_diff(file_diff);
+        }
+
+        let max_line_num = file_diff
+            .hunks
+            .iter()
+            .map(|hunk| (hunk.old_start + hunk.old_count).max(hunk.new_start + hunk.new_count))
+            .max()
+            .unwrap_or_default();
+        let half = (self.width - 3) / 2;
+        let num_width = max_line_num.to_string().len();
+        let layout = Layout { half, num_width, text_width: half.saturating_sub(num_width + 1).max(1) };
+
+        let mut output = String::new();
+
+        // 文件头：左栏为旧文件，右栏为新文件
+        let old_name = fit(&format!("--- {}", patch_path("a/", &file_diff.old_path)), half);
+        let new_name = fit(&format!("+++ {}", patch_path("b/", &file_diff.new_path)), half);
+        output.push_str(&self.paint(&old_name, "red", true, half));
+        output.push_str("   ");
+        output.push_str(&self.paint(&new_name, "green", true, 0));
+        output.push('\n');
+
+        for hunk in &file_diff.hunks {
+            let header = format!(
+                "@@ -{},{} +{},{} @@",
+                hunk.old_start, hunk.old_count, hunk.new_start, hunk.new_count
+            );
+            output.push_str(&self.formatter.colorize(&header, "cyan", true));
+            output.push('\n');
+
+            for row in rows(hunk) {
+                if row.marker == Marker::Common && self.suppress_common_lines {
+                    continue;
+                }
+                self.format_row(&row, &layout, &mut output);
+            }
+        }
+
+        output
+    }
+
+    /// Format a row, on several lines when wrapping
+    fn format_row(&self, row: &Row, layout: &Layout, output: &mut String) {
+        // 相同的行以 white 表示，不着色
+        let (marker, marker_color, old_color, new_color) = match row.marker {
+            Marker::Common if self.left_column => ('(', "white", "white", "white"),
+            Marker::Common => (' ', "white", "white", "white"),
+            Marker::Changed => ('|', "yellow", "red", "green"),
+            Marker::Deleted => ('<', "red", "red", "green"),
+            Marker::Added => ('>', "green", "red", "green"),
+        };
+        let show_new = !(row.marker == Marker::Common && self.left_column);
+
+        let old_pieces = row.old.as_ref().map(|cell| self.pieces(cell, layout.text_width)).unwrap_or_default();
+        let new_pieces = match &row.new {
+            Some(cell) if show_new => self.pieces(cell, layout.text_width),
+            _ => Vec::new(),
+        };
+        let line_count = old_pieces.len().max(new_pieces.len()).max(1);
+
+        for index in 0..line_count {
+            let mut line = String::new();
+
+            match &row.old {
+                Some(cell) => {
+                    line.push_str(&line_number(cell, index, layout.num_width));
+                    let piece = old_pieces.get(index).unwrap_or(&EMPTY_PIECE);
+                    line.push_str(&self.paint(piece, old_color, false, layout.text_width));
+                }
+                None => line.push_str(&" ".repeat(layout.half)),
+            }
+
+            line.push(' ');
+            match marker_color {
+                "white" => line.push(marker),
+                _ => line.push_str(&self.formatter.colorize(&marker.to_string(), marker_color, false)),
+            }
+
+            match (&row.new, new_pieces.get(index)) {
+                (Some(cell), Some(piece)) => {
+                    line.push(' ');
+                    line.push_str(&line_number(cell, index, layout.num_width));
+                    line.push_str(&self.paint(piece, new_color, false, 0));
+                }
+                (Some(cell), None) if show_new && index == 0 => {
+                    line.push(' ');
+                    line.push_str(&line_number(cell, index, layout.num_width));
+                }
+                _ => {}
+            }
+
+            output.push_str(line.trim_end_matches(' '));
+            output.push('\n');
+        }
+    }
+
+    /// Split a cell into pieces that fit the text column; only the first one
+    /// is kept when truncating
+    fn pieces(&self, cell: &Cell, width: usize) -> Vec<Piece> {
+        let mut spans: Vec<(&str, bool)> = match cell.segments {
+            Some(segments) if self.use_color => {
+                segments.iter().map(|segment| (segment.text.as_str(), segment.emphasized)).collect()
+            }
+            _ => vec![(cell.content, false)],
+        };
+        // CRLF 行末尾的 `\r` 不显示
+        if let Some((text, _)) = spans.last_mut() {
+            *text = text.strip_suffix('\r').unwrap_or(text);
+        }
+
+        let mut pieces = split(&spans, width);
+        if !self.wrap {
+            pieces.truncate(1);
+        }
+        pieces
+    }
+
+    /// Colorize a piece and pad it with spaces to `pad_to` columns
+    fn paint(&self, piece: &Piece, color: &str, bold: bool, pad_to: usize) -> String {
+        let mut text = String::new();
+        for (span, emphasized) in &piece.spans {
+            if *emphasized {
+                text.push_str(&self.formatter.emphasize(span, color));
+            } else if color == "white" {
+                text.push_str(span);
+            } else {
+                text.push_str(&self.formatter.colorize(span, color, bold));
+            }
+        }
+        text.push_str(&" ".repeat(pad_to.saturating_sub(piece.width)));
+        text
+    }
+}
+
+static EMPTY_PIECE: Piece = Piece { spans: Vec::new(), width: 0 };
+
+/// Line number on the first line of a cell, blank on continuation lines
+fn line_number(cell: &Cell, index: usize, num_width: usize) -> String {
+    match cell.line_num {
+        Some(line_num) if index == 0 => format!("{:>width$} ", line_num, width = num_width),
+        _ => " ".repeat(num_width + 1),
+    }
+}
+
+/// Pair the lines of a hunk into rows: a run of deletions followed by
+/// additions is shown as changed lines side by side, like GNU sdiff
+fn rows(hunk: &Hunk) -> Vec<Row<'_>> {
+    let mut rows = Vec::new();
+    let mut deleted: Vec<&LineChange> = Vec::new();
+    let mut added: Vec<&LineChange> = Vec::new();
+
+    for line in &hunk.lines {
+        match line.change_type {
+            ChangeType::Delete => {
+                deleted.push(line);
+                continue;
+            }
+            ChangeType::Add => {
+                added.push(line);
+                continue;
+            }
+            _ => {}
+        }
+
+        pair_changes(&mut rows, &mut deleted, &mut added);
+        match line.change_type {
+            ChangeType::Modify => {
+                let inline = line.inline.as_ref();
+                rows.push(Row {
+                    marker: Marker::Changed,
+                    old: Some(Cell {
+                        line_num: line.old_line_num,
+                        content: line.old_content.as_deref().unwrap_or_default(),
+                        segments: inline.map(|inline| &inline.old[..]),
+                    }),
+                    new: Some(Cell {
+                        line_num: line.new_line_num,
+                        content: &line.content,
+                        segments: inline.map(|inline| &inline.new[..]),
+                    }),
+                });
+            }
+            _ => rows.push(Row {
+                marker: Marker::Common,
+                old: Some(Cell {
+                    line_num: line.old_line_num,
+                    content: line.old_content.as_deref().unwrap_or(&line.content),
+                    segments: None,
+                }),
+                new: Some(Cell { line_num: line.new_line_num, content: &line.content, segments: None }),
+            }),
+        }
+    }
+    pair_changes(&mut rows, &mut deleted, &mut added);
+
+    rows
+}
+
+/// Turn the pending deletions and additions into rows
+fn pair_changes<'a>(rows: &mut Vec<Row<'a>>, deleted: &mut Vec<&'a LineChange>, added: &mut Vec<&'a LineChange>) {
+    let cell = |line_num: Option<usize>, line: &'a LineChange| Cell { line_num, content: &line.content, segments: None };
+    let paired = deleted.len().min(added.len());
+
+    for index in 0..deleted.len().max(added.len()) {
+        let old = deleted.get(index).map(|line| cell(line.old_line_num, line));
+        let new = added.get(index).map(|line| cell(line.new_line_num, line));
+        let marker = if index < paired {
+            Marker::Changed
+        } else if old.is_some() {
+            Marker::Deleted
+        } else {
+            Marker::Added
+        };
+        rows.push(Row { marker, old, new });
+    }
+
+    deleted.clear();
+    added.clear();
+}
+
+/// Truncate a text to `width` columns
+fn fit(text: &str, width: usize) -> Piece {
+    split(&[(text, false)], width).swap_remove(0)
+}
+
+/// Split spans of text into pieces of at most `width` columns.
+///
+/// Tabs are expanded to the next multiple of 8 columns and other control
+/// characters are shown as `?`. A wide character that does not fit at the end
+/// of a piece starts the next one.
+fn split(spans: &[(&str, bool)], width: usize) -> Vec<Piece> {
+    let mut pieces = vec![Piece::default()];
+    let mut column = 0;
+
+    let push = |pieces: &mut Vec<Piece>, ch: char, char_width: usize, emphasized: bool| {
+        if pieces.last().is_some_and(|piece| piece.width > 0 && piece.width + char_width > width) {
+            pieces.push(Piece::default());
+        }
+        let piece = pieces.last_mut().unwrap();
+        match piece.spans.last_mut() {
+            Some((text, last_emphasized)) if *last_emphasized == emphasized => text.push(ch),
+            _ => piece.spans.push((ch.to_string(), emphasized)),
+        }
+        piece.width += char_width;
+    };
+
+    for &(text, emphasized) in spans {
+        for ch in text.chars() {
+            if ch == '\t' {
+                for _ in 0..TAB_WIDTH - column % TAB_WIDTH {
+                    push(&mut pieces, ' ', 1, emphasized);
+                    column += 1;
+                }
+                continue;
+            }
+
+            let (ch, char_width) = if ch.is_control() { ('?', 1) } else { (ch, ch.width().unwrap_or(0)) };
+            push(&mut pieces, ch, char_width, emphasized);
+            column += char_width;
+        }
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::hunk::diff_lines;
+
+    fn file_diff(old: &[&str], new: &[&str]) -> FileDiff {
+        let mut file_diff = FileDiff::from_error("f", String::new());
+        file_diff.error = None;
+        let lines = diff_lines(old, new, 0, 0, &DiffOptions::default());
+        file_diff.hunks = vec![Hunk { old_start: 1, old_count: old.len(), new_start: 1, new_count: new.len(), lines }];
+        file_diff
+    }
+
+    fn text(piece: &Piece) -> String {
+        piece.spans.iter().map(|(text, _)| text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_split_wide_characters() {
+        let pieces = split(&[("日本語テキスト", false)], 5);
+        let texts: Vec<_> = pieces.iter().map(text).collect();
+        assert_eq!(texts, vec!["日本", "語テ", "キス", "ト"]);
+        assert_eq!(pieces[0].width, 4);
+
+        let pieces = split(&[("a\tb", false), ("cd", true)], 100);
+        assert_eq!(text(&pieces[0]), "a       bcd");
+        assert_eq!(pieces[0].spans[1], ("cd".to_string(), true));
+        assert_eq!(fit("abcdef", 3).width, 3);
+    }
+
+    #[test]
+    fn test_side_by_side() {
+        let file_diff = file_diff(&["same", "old line", "gone"], &["same", "new line", "more", "added"]);
+        let formatter = SideBySideFormatter::new(false).width(31);
+
+        assert_eq!(
+            formatter.format_file_diff(&file_diff),
+            "\
+--- a/f          +++ b/f
+@@ -1,3 +1,4 @@
+1 same           1 same
+2 old line     | 2 new line
+3 gone         | 3 more
+               > 4 added
+"
+        );
+
+        let suppressed = SideBySideFormatter::new(false).width(31).suppress_common_lines(true);
+        assert!(!suppressed.format_file_diff(&file_diff).contains("same"));
+        let left = SideBySideFormatter::new(false).width(31).left_column(true);
+        assert!(left.format_file_diff(&file_diff).contains("1 same         (\n"));
+    }
+
+    #[test]
+    fn test_wrap_long_lines() {
+        let file_diff = file_diff(&["0123456789abcdefghij"], &["short"]);
+
+        let truncated = SideBySideFormatter::new(false).width(31).format_file_diff(&file_diff);
+        assert!(truncated.contains("1 0123456789ab | 1 short\n"), "{}", truncated);
+
+        let wrapped = SideBySideFormatter::new(false).width(31).wrap(true).format_file_diff(&file_diff);
+        assert!(wrapped.contains("1 0123456789ab | 1 short\n  cdefghij     |\n"), "{}", wrapped);
+    }
+}
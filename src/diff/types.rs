@@ -1,4 +1,8 @@
+use encoding_rs::Encoding;
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+use crate::utils::input::is_stdin;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ChangeType {
@@ -14,6 +18,24 @@ pub struct LineChange {
     pub old_line_num: Option<usize>,
     pub new_line_num: Option<usize>,
     pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_content: Option<String>, // Modify 行的旧内容（content 为新内容）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inline: Option<InlineDiff>,  // Modify 行的行内差异
+}
+
+/// A piece of a modified line, emphasized when it differs from the other side
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InlineSegment {
+    pub text: String,
+    pub emphasized: bool,
+}
+
+/// Intra-line changes of a modified line, split for the old and new side
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InlineDiff {
+    pub old: Vec<InlineSegment>,
+    pub new: Vec<InlineSegment>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +58,200 @@ pub struct FileDiff {
     pub hunks: Vec<Hunk>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub full_content: Option<Vec<LineChange>>, // Complete file content for full view
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binary_diff: Option<BinaryDiff>, // --binary 模式下二进制文件的逐字节对比
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encodings: Option<FileEncodings>, // 任一侧不是 UTF-8 时记录两侧的编码
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_endings: Option<LineEndings>, // 两侧换行符不同时记录两侧的换行符
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missing_newline: Option<MissingNewline>, // 任一侧最后一行没有换行符
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modes: Option<FileModes>, // 补丁中记录的文件模式（git 扩展头）
+    #[serde(skip)]
+    pub mtimes: Option<FileTimes>, // 两侧文件的修改时间，用于 GNU 上下文格式的文件头
+    #[serde(default)]
+    pub status: FileStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<u8>, // 重命名/复制时新旧文件的相似度（百分比）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // 无法对比时的错误信息（如权限不足），此时 hunks 为空
+}
+
+impl FileDiff {
+    /// Entry for a file that could not be compared
+    pub fn from_error(path: &str, error: String) -> Self {
+        Self {
+            path: path.to_string(),
+            old_path: path.to_string(),
+            new_path: path.to_string(),
+            is_binary: false,
+            is_new: false,
+            is_deleted: false,
+            hunks: vec![],
+            full_content: None,
+            binary_diff: None,
+            encodings: None,
+            line_endings: None,
+            missing_newline: None,
+            modes: None,
+            mtimes: None,
+            status: FileStatus::Modified,
+            similarity: None,
+            error: Some(error),
+        }
+    }
+
+    /// Whether the file differs at all (binary files have no hunks)
+    pub fn has_changes(&self) -> bool {
+        !self.hunks.is_empty()
+            || self.is_binary
+            || self.is_new
+            || self.is_deleted
+            || self.encoding_changed()
+            || self.mode_changed()
+            || matches!(self.status, FileStatus::Renamed | FileStatus::Copied)
+    }
+
+    /// Whether the two sides were read with different encodings
+    pub fn encoding_changed(&self) -> bool {
+        self.encodings.as_ref().is_some_and(|encodings| encodings.old != encodings.new)
+    }
+
+    /// Whether the file mode changed, e.g. it became executable
+    pub fn mode_changed(&self) -> bool {
+        self.modes.as_ref().is_some_and(|modes| modes.old.is_some() && modes.new.is_some() && modes.old != modes.new)
+    }
+
+    /// Whether the file could not be compared
+    pub fn has_error(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// Count added and deleted lines; a modified line counts as one of each
+    pub fn count_changes(&self) -> (usize, usize) {
+        let mut additions = 0;
+        let mut deletions = 0;
+
+        for hunk in &self.hunks {
+            for line in &hunk.lines {
+                match line.change_type {
+                    ChangeType::Add => additions += 1,
+                    ChangeType::Delete => deletions += 1,
+                    ChangeType::Modify => {
+                        additions += 1;
+                        deletions += 1;
+                    }
+                    ChangeType::Context => {}
+                }
+            }
+        }
+
+        (additions, deletions)
+    }
+}
+
+/// How a file changed between the two sides
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    #[default]
+    Modified,
+    Added,
+    Deleted,
+    Renamed,  // old_path 被移动到 new_path
+    Copied,   // new_path 复制自仍然存在的 old_path
+}
+
+/// Encodings the two sides of a file were decoded with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEncodings {
+    pub old: String,
+    pub new: String,
+}
+
+/// Line terminator style of a text file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineEnding {
+    #[serde(rename = "LF")]
+    Lf,
+    #[serde(rename = "CRLF")]
+    Crlf,
+    Mixed,  // LF 和 CRLF 混用
+}
+
+impl std::fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineEnding::Lf => write!(f, "LF"),
+            LineEnding::Crlf => write!(f, "CRLF"),
+            LineEnding::Mixed => write!(f, "mixed"),
+        }
+    }
+}
+
+/// Line terminator styles of the two sides of a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineEndings {
+    pub old: LineEnding,
+    pub new: LineEnding,
+}
+
+/// Number of the last line of each side that has no trailing newline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MissingNewline {
+    pub old: Option<usize>,
+    pub new: Option<usize>,
+}
+
+/// Unix file modes of the two sides, such as `100644` or `100755`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileModes {
+    pub old: Option<String>,  // 新增文件没有旧模式
+    pub new: Option<String>,  // 删除文件没有新模式
+}
+
+/// Modification times of the two sides; standard input counts as modified now
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileTimes {
+    pub old: Option<SystemTime>,
+    pub new: Option<SystemTime>,
+}
+
+impl FileTimes {
+    /// Read the modification times of two inputs, `None` where unavailable
+    pub fn of(path1: &str, path2: &str) -> Self {
+        Self {
+            old: modified_time(path1),
+            new: modified_time(path2),
+        }
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    if is_stdin(path) {
+        return Some(SystemTime::now());
+    }
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Byte-level comparison of two binary files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryDiff {
+    pub old_size: u64,
+    pub new_size: u64,
+    pub first_difference: Option<u64>,  // 第一个不同字节的偏移量
+    pub differing_bytes: u64,           // 不同的字节数（含长度差）
+    pub regions: Vec<BinaryRegion>,
+    pub truncated: bool,                // 差异过多，regions 只包含开头部分
+}
+
+/// A run of differing 16-byte rows, with the bytes of each side at that offset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryRegion {
+    pub offset: u64,
+    pub old: Vec<u8>,  // 超出旧文件末尾的部分为空
+    pub new: Vec<u8>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,13 +260,206 @@ pub struct DiffResult {
     pub total_additions: usize,
     pub total_deletions: usize,
     pub total_files_changed: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merge: Option<MergeResult>, // 三方合并的结果，files 为 base 到两侧的 diff
+}
+
+impl DiffResult {
+    /// Collect compared files, keeping only those with changes or errors
+    pub fn from_files(file_diffs: impl IntoIterator<Item = FileDiff>) -> Self {
+        let mut files = Vec::new();
+        let mut total_additions = 0;
+        let mut total_deletions = 0;
+
+        for file_diff in file_diffs {
+            // Count additions and deletions
+            let (additions, deletions) = file_diff.count_changes();
+            total_additions += additions;
+            total_deletions += deletions;
+
+            // Only add if there are actual changes (or errors to report)
+            if file_diff.has_changes() || file_diff.has_error() {
+                files.push(file_diff);
+            }
+        }
+
+        Self {
+            total_files_changed: files.iter().filter(|file| !file.has_error()).count(),
+            files,
+            total_additions,
+            total_deletions,
+            merge: None,
+        }
+    }
+
+    /// Files that could not be compared
+    pub fn errors(&self) -> impl Iterator<Item = &FileDiff> {
+        self.files.iter().filter(|file| file.has_error())
+    }
+}
+
+/// Three-way merge of two files derived from a common base
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeResult {
+    pub base_path: String,
+    pub ours_path: String,
+    pub theirs_path: String,
+    pub conflict_style: ConflictStyle,
+    pub chunks: Vec<MergeChunk>,
+    pub conflicts: Vec<MergeConflict>,
+    pub merged: String, // 合并后的文本，冲突处带有冲突标记
+}
+
+impl MergeResult {
+    /// Whether the merge left conflicts to resolve by hand
+    pub fn has_conflicts(&self) -> bool {
+        !self.conflicts.is_empty()
+    }
+}
+
+/// How a region of the three files was merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeChunkKind {
+    Unchanged,  // 三方相同
+    Ours,       // 只有 ours 修改，采用 ours
+    Theirs,     // 只有 theirs 修改，采用 theirs
+    Both,       // 两侧做了相同的修改
+    Conflict,   // 两侧做了不同的修改
+}
+
+/// Lines of one file in a merge chunk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MergeLines {
+    pub start: usize,  // 第一行的行号（从 1 开始）；没有行时为前一行的行号加一
+    pub lines: Vec<String>,
+}
+
+/// A region where the base, ours and theirs either all agree or not
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeChunk {
+    pub kind: MergeChunkKind,
+    pub base: MergeLines,
+    pub ours: MergeLines,
+    pub theirs: MergeLines,
+}
+
+/// A conflicting chunk and where its markers start in the merged text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeConflict {
+    pub chunk: usize,         // 在 chunks 中的下标
+    pub merged_line: usize,   // "<<<<<<<" 所在的行号
+    pub base: MergeLines,
+    pub ours: MergeLines,
+    pub theirs: MergeLines,
+}
+
+/// How conflicts are written in the merged text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStyle {
+    Merge,   // 只有 ours 和 theirs
+    #[default]
+    Diff3,   // 另外包含 base 的内容
+    Zdiff3,  // 同 diff3，但两侧相同的开头和结尾移到冲突之外
+}
+
+/// What happened to each file of a patch applied with `rdiff apply`
+#[derive(Debug, Clone, Default)]
+pub struct ApplyReport {
+    pub files: Vec<AppliedFile>,
+}
+
+impl ApplyReport {
+    /// Number of hunks that could not be applied
+    pub fn rejected_hunks(&self) -> usize {
+        self.files.iter().map(AppliedFile::rejected_hunks).sum()
+    }
+
+    /// Files that could not be patched at all
+    pub fn errors(&self) -> impl Iterator<Item = &AppliedFile> {
+        self.files.iter().filter(|file| file.error.is_some())
+    }
+}
+
+/// A file patched by `rdiff apply`
+#[derive(Debug, Clone)]
+pub struct AppliedFile {
+    pub path: String,
+    pub status: FileStatus,
+    pub hunks: Vec<HunkOutcome>,
+    pub reject_file: Option<String>, // 保存被拒绝的 hunk 的 .rej 文件
+    pub error: Option<String>, // 无法打补丁时的错误信息（如文件不存在）
+}
+
+impl AppliedFile {
+    pub fn rejected_hunks(&self) -> usize {
+        self.hunks.iter().filter(|hunk| matches!(hunk, HunkOutcome::Rejected { .. })).count()
+    }
+}
+
+/// How one hunk of a patch was applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HunkOutcome {
+    /// Applied at `line` of the original file, `offset` lines away from where
+    /// the patch expected it, ignoring `fuzz` context lines at each end
+    Applied { line: usize, offset: isize, fuzz: usize },
+    /// The hunk's lines were not found near `line`
+    Rejected { line: usize },
 }
 
 #[derive(Debug, Clone)]
 pub struct DiffOptions {
     pub context_lines: usize,
-    pub ignore_whitespace: bool,
+    pub ignore_whitespace: bool,    // -w: 忽略所有空白
+    pub ignore_space_change: bool,  // -b: 忽略空白数量的变化
+    pub ignore_blank_lines: bool,   // -B: 忽略仅包含空行的改动
     pub ignore_case: bool,
+    pub strip_trailing_cr: bool,    // 忽略行尾的 CR
+    pub complete_last_line: bool,   // 缺少换行符的最后一行视为完整的行（ed 脚本无法表示缺失的换行）
+    pub binary: bool,               // 二进制文件逐字节对比，而不是只报告不同
+    pub encoding: Option<&'static Encoding>,  // 强制使用的编码，None 时自动检测
+    pub inline_mode: InlineMode,    // 行内差异的粒度
+    pub algorithm: DiffAlgorithm,
+    pub timeout: Option<Duration>,  // 单次 diff 的时间上限，超时后退化为近似结果
+}
+
+/// Line diff algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffAlgorithm {
+    Myers,
+    Patience,
+    Lcs,
+    Histogram,
+}
+
+/// Granularity of intra-line highlighting for modified lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InlineMode {
+    None,  // 不检测修改行，只输出删除/新增
+    Word,
+    Char,
+}
+
+/// Order of files in a directory diff result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    #[default]
+    Path,     // 按相对路径升序
+    Size,     // 按文件大小降序
+    Changes,  // 按改动行数降序
+    Status,   // 修改、重命名、复制、新增、删除、二进制、出错依次分组
+}
+
+impl DiffOptions {
+    /// Whether lines need to be normalized before comparison
+    pub fn needs_normalization(&self) -> bool {
+        self.ignore_whitespace
+            || self.ignore_space_change
+            || self.ignore_case
+            || self.strip_trailing_cr
+            || self.complete_last_line
+    }
 }
 
 impl Default for DiffOptions {
@@ -58,7 +467,16 @@ impl Default for DiffOptions {
         Self {
             context_lines: 3,
             ignore_whitespace: false,
+            ignore_space_change: false,
+            ignore_blank_lines: false,
             ignore_case: false,
+            strip_trailing_cr: false,
+            complete_last_line: false,
+            binary: false,
+            encoding: None,
+            inline_mode: InlineMode::Word,
+            algorithm: DiffAlgorithm::Myers,
+            timeout: None,
         }
     }
 }
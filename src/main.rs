@@ -1,77 +1,187 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Result, bail};
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use std::io::IsTerminal;
 use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
 
-mod cli;
-mod diff;
-mod utils;
-mod web;
-
-use cli::args::Args;
-use diff::{
-    directory::DirectoryDiffer,
+use rust_diff_tool::cli::args::{ApplyArgs, Args, Command, GitArgs, MergeArgs};
+use rust_diff_tool::diff::{
+    apply::PatchApplier,
+    directory::{DirectoryDiffer, PathFilter},
+    git::GitDiffer,
     formatter::DiffFormatter,
+    json,
     large_file::AdaptiveDiffer,
-    types::{DiffOptions, DiffResult},
+    merge::Merger,
+    patch::{pair_modified_lines, parse_patch},
+    side_by_side::{DEFAULT_WIDTH, SideBySideFormatter},
+    types::{ConflictStyle, DiffAlgorithm, DiffOptions, DiffResult, FileDiff, InlineMode, SortOrder},
 };
-use web::server::WebServer;
+use rust_diff_tool::utils::encoding::encoding_for_label;
+use rust_diff_tool::utils::git::{ExternalDiffArgs, GitSource};
+use rust_diff_tool::utils::input::{input_exists, read_input};
+use rust_diff_tool::web::{server::WebServer, templates::HtmlTemplate};
+
+/// 退出码与 GNU diff 一致
+const EXIT_SAME: u8 = 0;
+const EXIT_DIFFERENT: u8 = 1;
+const EXIT_TROUBLE: u8 = 2;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // 初始化日志
+async fn main() -> ExitCode {
+    // 解析命令行参数（参数错误时 clap 以退出码 2 退出）
+    let args = Args::parse();
+
+    // 初始化日志；--quiet 模式下除非显式设置 RUST_LOG，否则不输出日志
+    let default_filter = if args.quiet { "off" } else { "info" };
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(default_filter)),
         )
+        .with_writer(std::io::stderr) // 日志输出到 stderr，保持 stdout 只有 diff 结果
         .init();
 
-    // 解析命令行参数
-    let args = Args::parse();
+    match run(args).await {
+        Ok(status) => ExitCode::from(status),
+        Err(e) => {
+            eprintln!("❌ Error: {:#}", e);
+            ExitCode::from(EXIT_TROUBLE)
+        }
+    }
+}
 
+/// 执行对比并输出结果，返回退出码
+async fn run(args: Args) -> Result<u8> {
     // 创建 diff 选项
     let diff_options = DiffOptions {
         context_lines: args.unified_lines,
         ignore_whitespace: args.ignore_whitespace,
+        ignore_space_change: args.ignore_space_change,
+        ignore_blank_lines: args.ignore_blank_lines,
         ignore_case: args.ignore_case,
+        strip_trailing_cr: args.strip_trailing_cr,
+        // 与 GNU diff 一致，ed 脚本中缺少换行符的最后一行按完整的行对比
+        complete_last_line: args.ed,
+        binary: args.binary,
+        encoding: args.encoding.as_deref().map(encoding_for_label).transpose()?,
+        inline_mode: match args.inline.as_str() {
+            "none" => InlineMode::None,
+            "char" => InlineMode::Char,
+            _ => InlineMode::Word,
+        },
+        algorithm: match args.diff_algorithm.as_str() {
+            "patience" => DiffAlgorithm::Patience,
+            "lcs" => DiffAlgorithm::Lcs,
+            "histogram" => DiffAlgorithm::Histogram,
+            _ => DiffAlgorithm::Myers,
+        },
+        timeout: (args.diff_timeout > 0).then(|| Duration::from_secs(args.diff_timeout)),
     };
 
-    // 检查路径是否存在
-    let path1 = Path::new(&args.path1);
-    let path2 = Path::new(&args.path2);
+    let positional: Vec<String> = args
+        .path1
+        .iter()
+        .chain(&args.path2)
+        .chain(&args.git_external_args)
+        .cloned()
+        .collect();
+    let external_diff = args.git_external_diff || ExternalDiffArgs::is_protocol_len(positional.len());
 
-    if !path1.exists() && !path2.exists() {
-        eprintln!("❌ Error: Both paths do not exist");
-        std::process::exit(1);
-    }
+    // 输入为目录时 GNU 格式以目录路径加相对路径命名文件；git 和补丁同样包含多个文件，只在每个文件前输出 diff 行
+    let mut directories = None;
+    let mut file_headers = false;
 
-    // 执行 diff
-    let diff_result = if path1.is_dir() || path2.is_dir() {
-        // 目录对比
-        let differ = DirectoryDiffer::new(diff_options);
-        differ.compare_directories(&args.path1, &args.path2)?
-    } else {
-        // 文件对比 - 使用自适应策略自动优化大文件性能
-        let differ = AdaptiveDiffer::new(diff_options);
-        let file_diff = differ.diff_files(&args.path1, &args.path2)?;
-
-        // 计算统计信息
-        let (total_additions, total_deletions) = count_changes(&file_diff);
+    // JSON Lines 模式：git、文件和目录对比的每个文件对比完成即输出一行
+    let jsonl = args.format == "jsonl" && !args.quiet && args.output.is_none() && !args.web;
+    let mut jsonl_streamed = false;
+    let print_jsonl = |file: &FileDiff| -> Result<()> {
+        if jsonl {
+            println!("{}", json::to_json_line(file)?);
+        }
+        Ok(())
+    };
 
-        DiffResult {
-            files: vec![file_diff],
-            total_additions,
-            total_deletions,
-            total_files_changed: 1,
+    // 执行 diff
+    let diff_result = match &args.command {
+        Some(Command::Git(git_args)) => {
+            file_headers = true;
+            jsonl_streamed = true;
+            let (old, new) = git_sources(git_args)?;
+            GitDiffer::new(diff_options)
+                .pathspecs(git_args.paths.clone())
+                .fail_fast(args.fail_fast)
+                .compare_with(".", &old, &new, print_jsonl)?
         }
+        Some(Command::Merge(merge_args)) => merge_files(merge_args, diff_options)?,
+        // 打补丁不产生 diff 结果，直接报告每个文件的结果
+        Some(Command::Apply(apply_args)) => return apply_patch(apply_args, &args),
+        None if args.from_patch.is_some() || positional.is_empty() => {
+            // 没有路径参数时读取管道中的补丁，如 `git diff | rdiff --web`
+            let source = match &args.from_patch {
+                Some(source) => source.as_str(),
+                None if !std::io::stdin().is_terminal() => "-",
+                None => Args::command()
+                    .error(ErrorKind::MissingRequiredArgument, "PATH1 and PATH2 are required unless a patch is piped in")
+                    .exit(),
+            };
+            file_headers = true;
+            read_patch(source, diff_options.inline_mode)?
+        }
+        None if external_diff => {
+            // git 对每个改动的文件分别调用一次外部 diff 程序
+            let external_args = ExternalDiffArgs::parse(&positional)?;
+            let file_diff = GitDiffer::new(diff_options).compare_external(&external_args)?;
+            DiffResult::from_files([file_diff])
+        }
+        None => {
+            let [path1, path2] = positional.as_slice() else {
+                bail!("Expected two paths, got {} arguments", positional.len());
+            };
+            if Path::new(path1).is_dir() || Path::new(path2).is_dir() {
+                directories = Some((path1.as_str(), path2.as_str()));
+            }
+            jsonl_streamed = true;
+            compare_paths(&args, path1, path2, diff_options, print_jsonl)?
+        }
+    };
+
+    // 部分文件无法对比时与 GNU diff 一致返回 2
+    let status = if diff_result.errors().next().is_some() {
+        EXIT_TROUBLE
+    } else if let Some(merge) = &diff_result.merge {
+        // 与 diff3 一致：有冲突时返回 1
+        if merge.has_conflicts() { EXIT_DIFFERENT } else { EXIT_SAME }
+    } else if diff_result.files.is_empty() || external_diff {
+        // 外部 diff 程序返回非 0 时 git 会中止，因此有差异时也返回 0
+        EXIT_SAME
+    } else {
+        EXIT_DIFFERENT
     };
 
     // 输出结果
-    if args.web {
+    if args.quiet {
+        // 静默模式：只通过退出码报告结果
+    } else if let Some(output) = &args.output {
+        // 导出静态 HTML 报告，无需启动 Web 服务器
+        HtmlTemplate::export(&diff_result, output)?;
+        eprintln!("📄 HTML report written to {}", output);
+    } else if args.web {
         // Web 模式
         println!("🌐 Opening web browser to display diff...\n");
         let server = WebServer::new(diff_result, args.port);
         server.run().await?;
+    } else if args.format == "json" {
+        // JSON 模式：输出完整的结构化结果
+        println!("{}", json::to_json(&diff_result)?);
+    } else if args.format == "jsonl" {
+        // JSON Lines 模式：每行一个文件，对比时未输出的在此输出
+        if !jsonl_streamed {
+            for file in &diff_result.files {
+                print_jsonl(file)?;
+            }
+        }
     } else {
         // 命令行模式
         let use_color = match args.color.as_str() {
@@ -83,52 +193,245 @@ async fn main() -> Result<()> {
             }
         };
 
-        let formatter = DiffFormatter::new(use_color);
+        let mut formatter = DiffFormatter::new(use_color).file_headers(file_headers);
+        if let Some((old_root, new_root)) = directories {
+            formatter = formatter.directories(old_root, new_root);
+        }
 
-        if args.brief {
+        if let Some(merge) = &diff_result.merge {
+            // 合并模式：stdout 只输出合并后的文件，冲突数量输出到 stderr
+            print!("{}", merge.merged);
+            if merge.has_conflicts() {
+                eprintln!("⚠️  {} conflict(s)", merge.conflicts.len());
+            }
+        } else if args.brief {
             // 仅显示文件名
             if diff_result.files.is_empty() {
                 println!("✅ Files are identical");
             } else {
-                for file in &diff_result.files {
+                for file in diff_result.files.iter().filter(|file| !file.has_error()) {
                     println!("Files {} and {} differ", file.old_path, file.new_path);
                 }
+                eprint!("{}", formatter.format_errors(&diff_result));
             }
+        } else if args.normal || args.context || args.ed || args.rcs {
+            // GNU diff 的传统格式供其他工具读取：不输出提示和统计信息
+            let output = if args.context {
+                formatter.format_context(&diff_result)
+            } else if args.ed {
+                // ed 脚本无法表示缺失的末尾换行
+                for file in diff_result.files.iter().filter(|file| file.missing_newline.is_some()) {
+                    eprintln!("⚠️  {}: No newline at end of file", file.path);
+                }
+                formatter.format_ed(&diff_result)
+            } else if args.rcs {
+                formatter.format_rcs(&diff_result)
+            } else {
+                formatter.format_normal(&diff_result)
+            };
+            print!("{}", output);
+            eprint!("{}", formatter.format_errors(&diff_result));
         } else {
             // 显示完整 diff
             if diff_result.files.is_empty() {
-                println!("✅ No differences found");
+                if !external_diff {
+                    println!("✅ No differences found");
+                }
             } else {
-                let output = formatter.format_unified(&diff_result);
+                let output = if args.side_by_side {
+                    // 未指定宽度时使用终端宽度，输出重定向时与 GNU diff 一样使用 130
+                    let width = args.width.unwrap_or_else(|| {
+                        terminal_size::terminal_size().map_or(DEFAULT_WIDTH, |(width, _)| width.0 as usize)
+                    });
+                    SideBySideFormatter::new(use_color)
+                        .width(width)
+                        .left_column(args.left_column)
+                        .suppress_common_lines(args.suppress_common_lines)
+                        .wrap(args.wrap)
+                        .format(&diff_result)
+                } else {
+                    formatter.format_unified(&diff_result)
+                };
                 print!("{}", output);
 
-                // 显示统计信息
-                println!();
-                let summary = formatter.format_summary(&diff_result);
-                println!("{}", summary);
+                // 显示统计信息；git 逐个文件调用外部 diff 时不重复输出
+                if !external_diff {
+                    println!();
+                    let summary = formatter.format_summary(&diff_result);
+                    println!("{}", summary);
+                }
             }
         }
     }
 
-    Ok(())
+    Ok(status)
 }
 
-/// Count total additions and deletions in a file diff
-fn count_changes(file_diff: &diff::types::FileDiff) -> (usize, usize) {
-    let mut additions = 0;
-    let mut deletions = 0;
-
-    for hunk in &file_diff.hunks {
-        for line in &hunk.lines {
-            match line.change_type {
-                diff::types::ChangeType::Add => additions += 1,
-                diff::types::ChangeType::Delete => deletions += 1,
-                _ => {}
+/// 对比两个文件或目录（`-` 表示标准输入），每个有差异的文件对比完成即交给 `on_file`
+fn compare_paths(
+    args: &Args,
+    path1_arg: &str,
+    path2_arg: &str,
+    diff_options: DiffOptions,
+    mut on_file: impl FnMut(&FileDiff) -> Result<()>,
+) -> Result<DiffResult> {
+    // 检查路径是否存在
+    let path1 = Path::new(path1_arg);
+    let path2 = Path::new(path2_arg);
+
+    if !input_exists(path1) && !input_exists(path2) {
+        bail!("Both paths do not exist");
+    }
+
+    if path1.is_dir() || path2.is_dir() {
+        // 目录对比
+        let mut filter = PathFilter {
+            respect_ignore_files: !args.no_ignore,
+            exclude: args.exclude.clone(),
+            include: args.include.clone(),
+        };
+        for path in &args.exclude_from {
+            filter.add_excludes_from(path)?;
+        }
+
+        let differ = DirectoryDiffer::with_filter(diff_options, filter)
+            .jobs(args.jobs)
+            .fail_fast(args.fail_fast)
+            .find_renames(args.find_renames)
+            .find_copies(args.find_copies)
+            .sort(match args.sort.as_str() {
+                "size" => SortOrder::Size,
+                "changes" => SortOrder::Changes,
+                "status" => SortOrder::Status,
+                _ => SortOrder::Path,
+            })
+            .show_progress(!args.quiet);
+        differ.compare_directories_with(path1_arg, path2_arg, on_file)
+    } else {
+        // 文件对比 - 使用自适应策略自动优化大文件性能
+        let differ = AdaptiveDiffer::with_progress(diff_options, !args.quiet);
+        let mut file_diff = differ.diff_files(path1_arg, path2_arg)?;
+
+        // --label 替换显示的文件名（如 git difftool 的临时文件）
+        if args.label.len() > 2 {
+            bail!("--label can be given at most twice");
+        }
+        // 与 GNU diff 一致，标签同时替换文件头中的修改时间
+        if let Some(label) = args.label.first() {
+            file_diff.path = label.clone();
+            file_diff.old_path = label.clone();
+            if let Some(mtimes) = &mut file_diff.mtimes {
+                mtimes.old = None;
             }
         }
+        if let Some(label) = args.label.get(1) {
+            file_diff.new_path = label.clone();
+            if let Some(mtimes) = &mut file_diff.mtimes {
+                mtimes.new = None;
+            }
+        }
+
+        // 计算统计信息
+        let (total_additions, total_deletions) = file_diff.count_changes();
+
+        // 与目录对比一致：没有改动的文件不计入结果
+        let files = if !file_diff.has_changes() {
+            vec![]
+        } else {
+            on_file(&file_diff)?;
+            vec![file_diff]
+        };
+
+        Ok(DiffResult {
+            total_files_changed: files.len(),
+            files,
+            total_additions,
+            total_deletions,
+            merge: None,
+        })
+    }
+}
+
+/// 将补丁应用到目录 `rdiff apply PATCH [DIR]`，退出码与 GNU patch 一致
+fn apply_patch(apply_args: &ApplyArgs, args: &Args) -> Result<u8> {
+    let data = read_input(&apply_args.patch)?;
+    let text = String::from_utf8_lossy(&data);
+    let patch = parse_patch(&text, apply_args.strip)?;
+    if patch.files.is_empty() {
+        bail!("No file diffs found in {}", apply_args.patch);
+    }
+
+    let report = PatchApplier::new()
+        .fuzz(apply_args.fuzz)
+        .dry_run(apply_args.check)
+        .apply(&patch, &apply_args.dir);
+
+    if !args.quiet {
+        let use_color = args.color == "always" || (args.color == "auto" && atty::is(atty::Stream::Stdout));
+        print!("{}", DiffFormatter::new(use_color).format_apply_report(&report));
+    }
+
+    Ok(if report.errors().next().is_some() {
+        EXIT_TROUBLE
+    } else if report.rejected_hunks() > 0 {
+        EXIT_DIFFERENT
+    } else {
+        EXIT_SAME
+    })
+}
+
+/// 读取已有的 unified diff 或 git 补丁，配对修改行以便高亮行内差异
+fn read_patch(source: &str, inline_mode: InlineMode) -> Result<DiffResult> {
+    let data = read_input(source)?;
+    let text = String::from_utf8_lossy(&data);
+    let mut result = parse_patch(&text, 1)?;
+    for file_diff in &mut result.files {
+        pair_modified_lines(file_diff, inline_mode);
+    }
+
+    Ok(result)
+}
+
+/// 三方合并 `rdiff merge BASE OURS THEIRS`
+fn merge_files(merge_args: &MergeArgs, diff_options: DiffOptions) -> Result<DiffResult> {
+    if merge_args.label.len() > 3 {
+        bail!("--label can be given at most three times");
+    }
+
+    Merger::new(diff_options)
+        .conflict_style(match merge_args.conflict.as_str() {
+            "merge" => ConflictStyle::Merge,
+            "zdiff3" => ConflictStyle::Zdiff3,
+            _ => ConflictStyle::Diff3,
+        })
+        .labels(merge_args.label.clone())
+        .merge_files(&merge_args.base, &merge_args.ours, &merge_args.theirs)
+}
+
+/// 将 `rdiff git` 的参数映射为对比的两侧，与 git diff 的约定一致
+fn git_sources(git_args: &GitArgs) -> Result<(GitSource, GitSource)> {
+    let mut revisions = git_args.revisions.clone();
+    if let [range] = revisions.as_slice() {
+        if range.contains("...") {
+            bail!("Symmetric ranges (A...B) are not supported; use A..B or two revisions");
+        }
+        if let Some((old, new)) = range.split_once("..") {
+            let or_head = |rev: &str| if rev.is_empty() { "HEAD".to_string() } else { rev.to_string() };
+            revisions = vec![or_head(old), or_head(new)];
+        }
     }
 
-    (additions, deletions)
+    let revision = |rev: &String| GitSource::Revision(rev.clone());
+    let sources = match (git_args.cached, revisions.as_slice()) {
+        (false, []) => (GitSource::Index, GitSource::WorkingTree),
+        (false, [rev]) => (revision(rev), GitSource::WorkingTree),
+        (false, [old, new]) => (revision(old), revision(new)),
+        (true, []) => (GitSource::Revision("HEAD".to_string()), GitSource::Index),
+        (true, [rev]) => (revision(rev), GitSource::Index),
+        _ => bail!("--cached takes at most one revision"),
+    };
+
+    Ok(sources)
 }
 
 // 需要添加 atty 依赖来检测 TTY
@@ -0,0 +1,130 @@
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, UTF_8};
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use super::error::{DiffError, Result};
+
+/// Text decoded from a file, with the encoding it was read as
+#[derive(Debug)]
+pub struct DecodedText {
+    pub text: String,
+    pub encoding: &'static Encoding,
+}
+
+/// Look up an encoding by label, e.g. `latin1`, `shift_jis` or `utf-16le`
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| DiffError::EncodingError(format!("unknown encoding '{}'", label)))
+}
+
+/// Decode file content.
+///
+/// With `forced`, the content must be valid in that encoding. Otherwise the
+/// encoding comes from a BOM, then UTF-8 validity, then a statistical guess;
+/// content that fits none of them is read as UTF-8 with invalid bytes escaped.
+pub fn decode(bytes: &[u8], forced: Option<&'static Encoding>) -> Result<DecodedText> {
+    if let Some(encoding) = forced {
+        let (text, malformed) = encoding.decode_with_bom_removal(bytes);
+        if malformed {
+            return Err(DiffError::EncodingError(format!("content is not valid {}", encoding.name())));
+        }
+        return Ok(DecodedText {
+            text: text.into_owned(),
+            encoding,
+        });
+    }
+
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        let (text, malformed) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        if !malformed {
+            return Ok(DecodedText {
+                text: text.into_owned(),
+                encoding,
+            });
+        }
+    }
+
+    // 合法的 UTF-8，或者只有少量非法字节的 UTF-8（例如被截断的多字节字符）
+    if std::str::from_utf8(bytes).is_ok() || has_utf8_multibyte(bytes) {
+        return Ok(DecodedText {
+            text: decode_utf8_escaped(bytes).into_owned(),
+            encoding: UTF_8,
+        });
+    }
+
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, Utf8Detection::Deny);
+
+    let (text, malformed) = encoding.decode_without_bom_handling(bytes);
+    if malformed {
+        return Ok(DecodedText {
+            text: decode_utf8_escaped(bytes).into_owned(),
+            encoding: UTF_8,
+        });
+    }
+
+    Ok(DecodedText {
+        text: text.into_owned(),
+        encoding,
+    })
+}
+
+/// Decode UTF-8, writing invalid bytes as `\xNN` escapes so that no data is lost
+pub fn decode_utf8_escaped(bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut text = String::with_capacity(bytes.len() + 16);
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            let _ = write!(text, "\\x{:02x}", byte);
+        }
+    }
+
+    Cow::Owned(text)
+}
+
+/// Whether the bytes contain at least one valid non-ASCII UTF-8 character
+fn has_utf8_multibyte(bytes: &[u8]) -> bool {
+    bytes.utf8_chunks().any(|chunk| !chunk.valid().is_ascii())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_encodings() {
+        let utf8 = decode("héllo\n".as_bytes(), None).unwrap();
+        assert_eq!(utf8.encoding, UTF_8);
+        assert_eq!(utf8.text, "héllo\n");
+
+        let utf16 = decode(b"\xff\xfeh\0i\0\n\0", None).unwrap();
+        assert_eq!(utf16.encoding, encoding_rs::UTF_16LE);
+        assert_eq!(utf16.text, "hi\n");
+
+        let latin1 = decode(b"caf\xe9 cr\xe8me br\xfbl\xe9e\n", None).unwrap();
+        assert_eq!(latin1.encoding, encoding_rs::WINDOWS_1252);
+        assert_eq!(latin1.text, "café crème brûlée\n");
+
+        let forced = decode(b"\x82\xa0", Some(encoding_for_label("shift_jis").unwrap())).unwrap();
+        assert_eq!(forced.text, "あ");
+        assert!(decode(b"\xff\xff", Some(UTF_8)).is_err());
+        assert!(encoding_for_label("no-such-encoding").is_err());
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_escaped() {
+        assert_eq!(decode_utf8_escaped(b"ok"), "ok");
+        assert_eq!(decode_utf8_escaped(b"\xc3\xa9\xff"), "é\\xff");
+
+        // UTF-8 为主的内容中夹杂非法字节时不会被误判为其它编码
+        let decoded = decode(b"na\xc3\xafve \xff\n", None).unwrap();
+        assert_eq!(decoded.encoding, UTF_8);
+        assert_eq!(decoded.text, "naïve \\xff\n");
+    }
+}
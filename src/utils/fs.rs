@@ -1,8 +1,14 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use encoding_rs::{Encoding, UTF_8};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
+use super::encoding::{self, DecodedText};
+
+/// Only this many leading bytes are inspected to tell binary from text
+const BINARY_CHECK_BYTES: usize = 8000;
+
 /// Check if a file is binary
 pub fn is_binary_file(path: &str) -> Result<bool> {
     let path_obj = Path::new(path);
@@ -12,19 +18,69 @@ pub fn is_binary_file(path: &str) -> Result<bool> {
     }
 
     let mut file = File::open(path)?;
-    let mut buffer = vec![0; 8000]; // Read first 8KB
+    let mut buffer = vec![0; BINARY_CHECK_BYTES]; // Read first 8KB
 
     let bytes_read = file.read(&mut buffer)?;
     buffer.truncate(bytes_read);
 
+    // UTF-16 文本要完整解码才能确定不是二进制
+    if utf16_bom(&buffer).is_some() {
+        return Ok(is_binary(&std::fs::read(path)?));
+    }
+
+    Ok(is_binary(&buffer))
+}
+
+/// Check if content is binary, looking at its first 8KB.
+///
+/// UTF-16 text contains null bytes too; content starting with a UTF-16 BOM is
+/// text when all of it decodes, and binary otherwise.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    if let Some((encoding, bom_length)) = utf16_bom(bytes) {
+        return encoding
+            .decode_without_bom_handling_and_without_replacement(&bytes[bom_length..])
+            .is_none();
+    }
+
     // Check for null bytes (common in binary files)
-    Ok(buffer.iter().any(|&b| b == 0))
+    let head = &bytes[..bytes.len().min(BINARY_CHECK_BYTES)];
+    head.contains(&0)
+}
+
+/// UTF-16 encoding and BOM length of content starting with a UTF-16 BOM
+fn utf16_bom(bytes: &[u8]) -> Option<(&'static Encoding, usize)> {
+    Encoding::for_bom(bytes).filter(|(encoding, _)| *encoding != UTF_8)
+}
+
+/// Check whether two files have exactly the same bytes
+pub fn files_identical(path1: &str, path2: &str) -> Result<bool> {
+    if std::fs::metadata(path1)?.len() != std::fs::metadata(path2)?.len() {
+        return Ok(false);
+    }
+
+    let mut file1 = File::open(path1)?;
+    let mut file2 = File::open(path2)?;
+    let mut buffer1 = vec![0; 64 * 1024];
+    let mut buffer2 = vec![0; 64 * 1024];
+
+    loop {
+        let bytes_read = file1.read(&mut buffer1)?;
+        if bytes_read == 0 {
+            return Ok(true);
+        }
+        file2.read_exact(&mut buffer2[..bytes_read])?;
+
+        if buffer1[..bytes_read] != buffer2[..bytes_read] {
+            return Ok(false);
+        }
+    }
 }
 
-/// Read file content with encoding detection
-pub fn read_file_with_encoding(path: &str) -> Result<String> {
-    let content = std::fs::read_to_string(path)?;
-    Ok(content)
+/// Read file content with encoding detection, or in the `forced` encoding
+pub fn read_file_with_encoding(path: &str, forced: Option<&'static Encoding>) -> Result<DecodedText> {
+    let bytes = std::fs::read(path)?;
+    let decoded = encoding::decode(&bytes, forced).with_context(|| format!("Cannot decode {}", path))?;
+    Ok(decoded)
 }
 
 /// Get file extension
@@ -34,3 +90,22 @@ pub fn get_file_extension(path: &str) -> Option<String> {
         .and_then(|s| s.to_str())
         .map(|s| s.to_lowercase())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_binary() {
+        assert!(!is_binary(b"plain text\n"));
+        assert!(is_binary(b"\0\x01\x02"));
+
+        // 带 BOM 的 UTF-16 文本含有 0 字节，但可以完整解码
+        assert!(!is_binary(b"\xff\xfeh\0i\0\n\0"));
+        assert!(!is_binary(b"\xfe\xff\0h\0i\0\n"));
+
+        // BOM 之后无法按 UTF-16 解码（奇数长度、孤立的代理项）的内容是二进制
+        assert!(is_binary(b"\xff\xfe\x00"));
+        assert!(is_binary(b"\xff\xfe\x00\xd8a\0"));
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// One side of a git comparison
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitSource {
+    /// A commit, tag, branch or any other revision `git rev-parse` understands
+    Revision(String),
+    /// The staging area
+    Index,
+    /// Files on disk
+    WorkingTree,
+}
+
+impl std::fmt::Display for GitSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GitSource::Revision(rev) => write!(f, "{}", rev),
+            GitSource::Index => write!(f, "index"),
+            GitSource::WorkingTree => write!(f, "working tree"),
+        }
+    }
+}
+
+/// How a path changed between two sources (from `git diff --name-status`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GitChange {
+    Added,
+    Deleted,
+    Modified,
+}
+
+/// A path that differs between two sources, relative to the repository root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedPath {
+    pub path: String,
+    pub change: GitChange,
+}
+
+/// Arguments git passes to an external diff program (`GIT_EXTERNAL_DIFF` or
+/// `diff.external`):
+/// `path old-file old-hex old-mode new-file new-hex new-mode`, followed by
+/// `new-path info` when the file was renamed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalDiffArgs {
+    /// Path of the file in the repository
+    pub path: String,
+    /// Temporary file with the old content, `/dev/null` for added files
+    pub old_file: String,
+    /// Temporary file with the new content, `/dev/null` for deleted files
+    pub new_file: String,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    /// New path of a renamed file
+    pub new_path: Option<String>,
+    /// Similarity of a renamed file, from the `similarity index N%` info line
+    pub similarity: Option<u8>,
+}
+
+impl ExternalDiffArgs {
+    /// Whether this many positional arguments follow the external diff protocol
+    pub fn is_protocol_len(len: usize) -> bool {
+        len == 7 || len == 9
+    }
+
+    pub fn parse(args: &[String]) -> Result<Self> {
+        if !Self::is_protocol_len(args.len()) {
+            bail!("git external diff expects 7 or 9 arguments, got {}", args.len());
+        }
+
+        // 缺失的一侧模式为 "."
+        let mode = |mode: &String| (mode != ".").then(|| mode.clone());
+        let similarity = args.get(8).and_then(|info| {
+            info.lines()
+                .find_map(|line| line.strip_prefix("similarity index "))
+                .and_then(|percent| percent.trim_end_matches('%').parse().ok())
+        });
+
+        Ok(Self {
+            path: args[0].clone(),
+            old_file: args[1].clone(),
+            old_mode: mode(&args[3]),
+            new_file: args[4].clone(),
+            new_mode: mode(&args[6]),
+            new_path: args.get(7).cloned(),
+            similarity,
+        })
+    }
+}
+
+/// A local git repository, accessed by running the `git` executable
+pub struct GitRepo {
+    root: PathBuf,
+    /// Directory the repository was opened from, where pathspecs are resolved
+    dir: PathBuf,
+}
+
+impl GitRepo {
+    /// Open the repository containing `dir`
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let output = run_git(dir.as_ref(), &["rev-parse", "--show-toplevel"])
+            .with_context(|| format!("{} is not inside a git working tree", dir.as_ref().display()))?;
+        let root = String::from_utf8_lossy(&output).trim_end_matches(['\r', '\n']).to_string();
+
+        Ok(Self {
+            root: PathBuf::from(root),
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Root directory of the working tree
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Check that a revision exists, so that typos fail before any comparison
+    pub fn verify_revision(&self, rev: &str) -> Result<()> {
+        run_git(&self.root, &["rev-parse", "--verify", "--quiet", "--end-of-options", &format!("{}^{{commit}}", rev)])
+            .with_context(|| format!("Unknown revision {}", rev))?;
+        Ok(())
+    }
+
+    /// Paths that differ between two sources, optionally limited to pathspecs.
+    ///
+    /// Pathspecs are relative to the directory the repository was opened from,
+    /// as in `git diff`; the returned paths are relative to the root. Renames
+    /// are reported as a deletion and an addition. Untracked files are not
+    /// included.
+    pub fn changed_paths(&self, old: &GitSource, new: &GitSource, pathspecs: &[String]) -> Result<Vec<ChangedPath>> {
+        // --no-relative：即使配置了 diff.relative，输出的路径也相对于仓库根目录
+        let mut args = vec!["diff", "--name-status", "-z", "--no-renames", "--no-ext-diff", "--no-relative"];
+        match (old, new) {
+            (GitSource::Index, GitSource::WorkingTree) => {}
+            (GitSource::Revision(rev), GitSource::WorkingTree) => args.push(rev),
+            (GitSource::Revision(rev), GitSource::Index) => args.extend(["--cached", rev.as_str()]),
+            (GitSource::Revision(old_rev), GitSource::Revision(new_rev)) => args.extend([old_rev.as_str(), new_rev.as_str()]),
+            _ => bail!("Cannot compare {} with {}", old, new),
+        }
+        args.push("--");
+        args.extend(pathspecs.iter().map(String::as_str));
+
+        let output = run_git(&self.dir, &args)?;
+        parse_name_status(&output)
+    }
+
+    /// Content of a path in a source, `None` if it does not exist there
+    pub fn read(&self, source: &GitSource, path: &str) -> Result<Option<Vec<u8>>> {
+        let object = match source {
+            GitSource::WorkingTree => {
+                let full_path = self.root.join(path);
+                if !full_path.exists() {
+                    return Ok(None);
+                }
+                let bytes = std::fs::read(&full_path).with_context(|| format!("Cannot read {}", full_path.display()))?;
+                return Ok(Some(bytes));
+            }
+            GitSource::Index => format!(":{}", path),
+            GitSource::Revision(rev) => format!("{}:{}", rev, path),
+        };
+
+        let bytes = run_git(&self.root, &["cat-file", "blob", &object]).with_context(|| format!("Cannot read {} from {}", path, source))?;
+        Ok(Some(bytes))
+    }
+}
+
+/// Run git in `dir` and return its stdout, failing with its stderr
+fn run_git(dir: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .context("Cannot run git; is it installed and on PATH?")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git {} failed: {}", args.first().unwrap_or(&""), stderr.trim());
+    }
+
+    Ok(output.stdout)
+}
+
+/// Parse `git diff --name-status -z` output: `STATUS\0PATH\0` records
+fn parse_name_status(output: &[u8]) -> Result<Vec<ChangedPath>> {
+    let mut fields = output.split(|&b| b == 0).filter(|field| !field.is_empty());
+    let mut changes = Vec::new();
+
+    while let Some(status) = fields.next() {
+        let path = fields.next().context("Malformed git diff output")?;
+        let change = match status.first() {
+            Some(b'A') => GitChange::Added,
+            Some(b'D') => GitChange::Deleted,
+            // M、T（类型变化）、U（未合并）等都按修改处理
+            _ => GitChange::Modified,
+        };
+        changes.push(ChangedPath {
+            path: String::from_utf8_lossy(path).into_owned(),
+            change,
+        });
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_external_diff_args() {
+        let args: Vec<String> = ["f.txt", "/tmp/a", "abc", "100644", "f.txt", "def", "100755"].map(String::from).to_vec();
+        let parsed = ExternalDiffArgs::parse(&args).unwrap();
+        assert_eq!(parsed.old_mode.as_deref(), Some("100644"));
+        assert_eq!(parsed.new_file, "f.txt");
+        assert_eq!(parsed.new_path, None);
+
+        let args: Vec<String> = [
+            "old.txt",
+            "/tmp/a",
+            "abc",
+            "100644",
+            "/tmp/b",
+            "def",
+            "100644",
+            "new.txt",
+            "similarity index 90%\nrename from old.txt\nrename to new.txt\n",
+        ]
+        .map(String::from)
+        .to_vec();
+        let parsed = ExternalDiffArgs::parse(&args).unwrap();
+        assert_eq!(parsed.new_path.as_deref(), Some("new.txt"));
+        assert_eq!(parsed.similarity, Some(90));
+
+        assert!(ExternalDiffArgs::parse(&args[..2]).is_err());
+    }
+
+    #[test]
+    fn test_parse_name_status() {
+        let changes = parse_name_status(b"M\0src/main.rs\0A\0new file.txt\0D\0old.txt\0").unwrap();
+
+        assert_eq!(
+            changes,
+            vec![
+                ChangedPath { path: "src/main.rs".to_string(), change: GitChange::Modified },
+                ChangedPath { path: "new file.txt".to_string(), change: GitChange::Added },
+                ChangedPath { path: "old.txt".to_string(), change: GitChange::Deleted },
+            ]
+        );
+        assert!(parse_name_status(b"M\0").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Deref;
+use std::path::Path;
+
+/// Path argument that stands for standard input
+pub const STDIN_PATH: &str = "-";
+
+/// Raw content of a diff input
+pub enum InputData {
+    /// Regular file, memory-mapped
+    Mapped(Mmap),
+    /// Standard input, a pipe or another stream, read into memory
+    Buffered(Vec<u8>),
+}
+
+impl Deref for InputData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            InputData::Mapped(mmap) => mmap,
+            InputData::Buffered(bytes) => bytes,
+        }
+    }
+}
+
+/// Whether the path is `-` (standard input)
+pub fn is_stdin<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().as_os_str() == STDIN_PATH
+}
+
+/// Whether the input can be read: `-` or an existing path
+pub fn input_exists<P: AsRef<Path>>(path: P) -> bool {
+    is_stdin(&path) || path.as_ref().exists()
+}
+
+/// Read a diff input.
+///
+/// `-` is standard input and regular files are memory-mapped. Anything else,
+/// such as a named pipe or `/dev/fd/63` from process substitution, can only be
+/// read once and is read to the end.
+pub fn read_input<P: AsRef<Path>>(path: P) -> Result<InputData> {
+    let path = path.as_ref();
+    let read = || -> std::io::Result<InputData> {
+        if is_stdin(path) {
+            let mut bytes = Vec::new();
+            std::io::stdin().lock().read_to_end(&mut bytes)?;
+            return Ok(InputData::Buffered(bytes));
+        }
+
+        let mut file = File::open(path)?;
+        if file.metadata()?.is_file() {
+            return Ok(InputData::Mapped(unsafe { Mmap::map(&file)? }));
+        }
+
+        // 管道、字符设备等无法映射，也无法预先知道长度
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        Ok(InputData::Buffered(bytes))
+    };
+
+    read().with_context(|| format!("Cannot read {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_read_input() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"a\nb\n").unwrap();
+
+        let data = read_input(file.path()).unwrap();
+        assert!(matches!(data, InputData::Mapped(_)));
+        assert_eq!(&*data, b"a\nb\n");
+
+        assert!(input_exists(STDIN_PATH));
+        assert!(!input_exists("/nonexistent/rdiff"));
+        let err = read_input("/nonexistent/rdiff").err().unwrap();
+        assert!(err.to_string().contains("/nonexistent/rdiff"));
+    }
+}
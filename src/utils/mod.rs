@@ -1,2 +1,6 @@
+pub mod encoding;
 pub mod error;
 pub mod fs;
+pub mod git;
+pub mod input;
+pub mod parallel;
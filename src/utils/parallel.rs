@@ -0,0 +1,100 @@
+use anyhow::{Result, anyhow};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+
+/// Map `items` in parallel and pass the results to `emit` in input order,
+/// each as soon as it and every result before it are ready.
+///
+/// `jobs` is the number of threads (0: one per CPU core). Stops at the first
+/// error returned by `map` or `emit`.
+pub fn map_in_order<T, R>(
+    items: &[T],
+    jobs: usize,
+    map: impl Fn(&T) -> Result<R> + Sync,
+    mut emit: impl FnMut(R) -> Result<()>,
+) -> Result<()>
+where
+    T: Sync,
+    R: Send,
+{
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        let worker = scope.spawn(|| {
+            let map_all = || {
+                items.par_iter().enumerate().try_for_each_with(sender, |sender, (i, item)| {
+                    // 接收端提前退出（emit 出错）时停止其余任务
+                    sender.send((i, map(item)?)).map_err(|_| anyhow!("Result receiver stopped"))
+                })
+            };
+            if jobs > 0 {
+                rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?.install(map_all)
+            } else {
+                map_all()
+            }
+        });
+
+        // 先完成的结果暂存，直到它之前的结果都已交付
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        let emitted = receiver.into_iter().try_for_each(|(i, result)| {
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&next) {
+                emit(result)?;
+                next += 1;
+            }
+            Ok(())
+        });
+
+        // emit 的错误优先：此时工作线程只会报告接收端已退出
+        let mapped = worker.join().map_err(|_| anyhow!("Worker thread panicked"))?;
+        emitted.and(mapped)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_results_are_emitted_in_input_order() {
+        let items: Vec<u64> = (0..100).collect();
+        for jobs in [0, 1, 4] {
+            let mut emitted = Vec::new();
+            map_in_order(
+                &items,
+                jobs,
+                |&i| {
+                    // 靠前的任务更慢，迫使结果乱序完成
+                    std::thread::sleep(Duration::from_micros((100 - i) * 20));
+                    Ok(i * 2)
+                },
+                |result| {
+                    emitted.push(result);
+                    Ok(())
+                },
+            )
+            .unwrap();
+            assert_eq!(emitted, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_errors_stop_the_run() {
+        let items: Vec<u64> = (0..100).collect();
+
+        let err = map_in_order(&items, 0, |&i| if i == 50 { Err(anyhow!("map failed")) } else { Ok(i) }, |_| Ok(())).unwrap_err();
+        assert_eq!(err.to_string(), "map failed");
+
+        let mut emitted = 0;
+        let err = map_in_order(&items, 0, |&i| Ok(i), |i| {
+            emitted += 1;
+            if i == 10 { Err(anyhow!("emit failed")) } else { Ok(()) }
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "emit failed");
+        assert_eq!(emitted, 11);
+    }
+}
@@ -103,6 +103,12 @@ pub fn get_css() -> &'static str {
         flex: 1;
     }
 
+    .file-badge {
+        margin-right: 0.75rem;
+        color: #9a6700;
+        font-size: 0.75rem;
+    }
+
     .file-actions {
         display: flex;
         gap: 0.5rem;
@@ -173,6 +179,36 @@ pub fn get_css() -> &'static str {
         background: white;
     }
 
+    /* Marker after a last line without trailing newline */
+    .no-newline {
+        margin-left: 0.5rem;
+        color: #57606a;
+        font-style: italic;
+    }
+
+    /* Intra-line changes of modified lines */
+    .inline-del {
+        background: #ff818266;
+        border-radius: 2px;
+    }
+
+    .inline-add {
+        background: #abf2bc;
+        border-radius: 2px;
+    }
+
+    /* Byte-level binary diff (--binary) */
+    .binary-summary {
+        padding: 0.5rem 1rem;
+        color: #57606a;
+        font-size: 0.875rem;
+    }
+
+    .diff-table .hex-offset {
+        width: 90px;
+        min-width: 90px;
+    }
+
     /* Side-by-side view styles */
     .side-by-side {
         display: flex;
@@ -194,6 +230,35 @@ pub fn get_css() -> &'static str {
         table-layout: auto;
     }
 
+    /* Three-way merge (rdiff merge): ours, base and theirs side by side */
+    .merge-table .line-content {
+        border-right: 1px solid #d0d7de;
+    }
+
+    .merge-pane-header td {
+        background: #f6f8fa;
+        border-bottom: 1px solid #d0d7de;
+        font-weight: 600;
+        padding: 0.25rem 0.5rem;
+    }
+
+    .merge-conflict-header td {
+        background: #fff8c5;
+        color: #9a6700;
+        font-weight: 600;
+        padding: 0.25rem 0.5rem;
+    }
+
+    .merge-table td.merge-conflict {
+        background: #fff8c5;
+    }
+
+    .merge-skipped td {
+        color: #57606a;
+        background: #f6f8fa;
+        text-align: center;
+    }
+
     /* Loading state */
     .loading {
         text-align: center;
@@ -271,6 +336,9 @@ pub fn get_js() -> &'static str {
         });
 
         function renderDiff(view) {
+            // 三方合并时在各侧的 diff 之前显示三栏视图
+            const mergeHtml = diffData.merge ? generateMergeView(diffData.merge) : '';
+
             // 计算总行数
             const totalLines = diffData.files.reduce((total, file) => {
                 if (file.is_binary) return total;
@@ -280,16 +348,16 @@ pub fn get_js() -> &'static str {
             // 如果行数超过阈值，使用虚拟滚动
             if (totalLines > VIRTUAL_SCROLL_THRESHOLD) {
                 if (view === 'unified') {
-                    container.innerHTML = generateVirtualUnifiedView(diffData, totalLines);
+                    container.innerHTML = mergeHtml + generateVirtualUnifiedView(diffData, totalLines);
                 } else {
                     // 并排视图暂不支持虚拟滚动，使用常规渲染
-                    container.innerHTML = generateSideBySideView(diffData);
+                    container.innerHTML = mergeHtml + generateSideBySideView(diffData);
                 }
             } else {
                 if (view === 'unified') {
-                    container.innerHTML = generateUnifiedView(diffData);
+                    container.innerHTML = mergeHtml + generateUnifiedView(diffData);
                 } else {
-                    container.innerHTML = generateSideBySideView(diffData);
+                    container.innerHTML = mergeHtml + generateSideBySideView(diffData);
                 }
             }
         }
@@ -306,9 +374,11 @@ pub fn get_js() -> &'static str {
                 if (!file.is_binary) {
                     file.hunks.forEach(hunk => {
                         hunk.lines.forEach(line => {
-                            allLines.push({
-                                type: 'line',
-                                line: line
+                            unifiedRows(line, file).forEach(row => {
+                                allLines.push({
+                                    type: 'line',
+                                    row: row
+                                });
                             });
                         });
                     });
@@ -355,17 +425,12 @@ pub fn get_js() -> &'static str {
                                 <td colspan="3" class="file-header" style="padding: 0.5rem;">${escapeHtml(item.content)}</td>
                             </tr>`;
                         } else {
-                            const line = item.line;
-                            const changeClass = line.change_type === 'Add' ? 'line-add' :
-                                              line.change_type === 'Delete' ? 'line-delete' :
-                                              'line-context';
-                            const prefix = line.change_type === 'Add' ? '+' :
-                                         line.change_type === 'Delete' ? '-' : ' ';
-
-                            html += `<tr class="${changeClass}" style="position: absolute; top: ${top}px; left: 0; right: 0; height: ${ROW_HEIGHT}px;">
-                                <td class="line-num" style="width: 60px;">${line.old_line_num || ''}</td>
-                                <td class="line-num" style="width: 60px;">${line.new_line_num || ''}</td>
-                                <td class="line-content">${prefix}${escapeHtml(line.content)}</td>
+                            const row = item.row;
+
+                            html += `<tr class="${row.changeClass}" style="position: absolute; top: ${top}px; left: 0; right: 0; height: ${ROW_HEIGHT}px;">
+                                <td class="line-num" style="width: 60px;">${row.oldLineNum || ''}</td>
+                                <td class="line-num" style="width: 60px;">${row.newLineNum || ''}</td>
+                                <td class="line-content">${row.prefix}${row.html}</td>
                             </tr>`;
                         }
                     }
@@ -397,6 +462,7 @@ pub fn get_js() -> &'static str {
                 html += `<div class="file-diff">`;
                 html += `<div class="file-header">`;
                 html += `<span class="file-name">${escapeHtml(file.path)}</span>`;
+                html += fileBadges(file);
                 html += `<div class="file-actions">`;
 
                 // Only show the button if full_content is available
@@ -409,7 +475,11 @@ pub fn get_js() -> &'static str {
                 html += `</div>`;
                 html += `</div>`;
 
-                if (file.is_binary) {
+                if (file.error) {
+                    html += `<div style="padding: 1rem; color: #cf222e;">Cannot compare: ${escapeHtml(file.error)}</div>`;
+                } else if (file.binary_diff) {
+                    html += renderBinaryDiff(file.binary_diff);
+                } else if (file.is_binary) {
                     html += `<div style="padding: 1rem; color: #57606a;">Binary file - cannot display diff</div>`;
                 } else {
                     html += `<table class="diff-table">`;
@@ -428,17 +498,13 @@ pub fn get_js() -> &'static str {
                         // Show diff only
                         file.hunks.forEach(hunk => {
                             hunk.lines.forEach(line => {
-                                const changeClass = line.change_type === 'Add' ? 'line-add' :
-                                                  line.change_type === 'Delete' ? 'line-delete' :
-                                                  'line-context';
-                                const prefix = line.change_type === 'Add' ? '+' :
-                                             line.change_type === 'Delete' ? '-' : ' ';
-
-                                html += `<tr class="${changeClass}">`;
-                                html += `<td class="line-num">${line.old_line_num || ''}</td>`;
-                                html += `<td class="line-num">${line.new_line_num || ''}</td>`;
-                                html += `<td class="line-content">${prefix}${escapeHtml(line.content)}</td>`;
-                                html += `</tr>`;
+                                unifiedRows(line, file).forEach(row => {
+                                    html += `<tr class="${row.changeClass}">`;
+                                    html += `<td class="line-num">${row.oldLineNum || ''}</td>`;
+                                    html += `<td class="line-num">${row.newLineNum || ''}</td>`;
+                                    html += `<td class="line-content">${row.prefix}${row.html}</td>`;
+                                    html += `</tr>`;
+                                });
                             });
                         });
                     }
@@ -458,6 +524,7 @@ pub fn get_js() -> &'static str {
                 html += `<div class="file-diff">`;
                 html += `<div class="file-header">`;
                 html += `<span class="file-name">${escapeHtml(file.path)}</span>`;
+                html += fileBadges(file);
                 html += `<div class="file-actions">`;
 
                 // Only show the button if full_content is available
@@ -470,7 +537,11 @@ pub fn get_js() -> &'static str {
                 html += `</div>`;
                 html += `</div>`;
 
-                if (file.is_binary) {
+                if (file.error) {
+                    html += `<div style="padding: 1rem; color: #cf222e;">Cannot compare: ${escapeHtml(file.error)}</div>`;
+                } else if (file.binary_diff) {
+                    html += renderBinaryDiff(file.binary_diff);
+                } else if (file.is_binary) {
                     html += `<div style="padding: 1rem; color: #57606a;">Binary file - cannot display diff</div>`;
                 } else if (showFullFile && file.full_content && file.full_content.length > 0) {
                     // Full file view: show entire file (no side-by-side for full view, just unified)
@@ -492,10 +563,10 @@ pub fn get_js() -> &'static str {
                     file.hunks.forEach(hunk => {
                         hunk.lines.forEach(line => {
                             if (line.change_type !== 'Add') {
-                                const changeClass = line.change_type === 'Delete' ? 'line-delete' : 'line-context';
+                                const changeClass = line.change_type === 'Context' ? 'line-context' : 'line-delete';
                                 html += `<tr class="${changeClass}">`;
                                 html += `<td class="line-num">${line.old_line_num || ''}</td>`;
-                                html += `<td class="line-content">${escapeHtml(line.content)}</td>`;
+                                html += `<td class="line-content">${renderContent(line, 'old')}${eofMarker(file, line, 'old')}</td>`;
                                 html += `</tr>`;
                             } else {
                                 // Add empty row for alignment
@@ -512,10 +583,10 @@ pub fn get_js() -> &'static str {
                     file.hunks.forEach(hunk => {
                         hunk.lines.forEach(line => {
                             if (line.change_type !== 'Delete') {
-                                const changeClass = line.change_type === 'Add' ? 'line-add' : 'line-context';
+                                const changeClass = line.change_type === 'Context' ? 'line-context' : 'line-add';
                                 html += `<tr class="${changeClass}">`;
                                 html += `<td class="line-num">${line.new_line_num || ''}</td>`;
-                                html += `<td class="line-content">${escapeHtml(line.content)}</td>`;
+                                html += `<td class="line-content">${renderContent(line, 'new')}${eofMarker(file, line, 'new')}</td>`;
                                 html += `</tr>`;
                             } else {
                                 // Add empty row for alignment
@@ -532,6 +603,201 @@ pub fn get_js() -> &'static str {
             return html || '<div class="loading">No differences found</div>';
         }
 
+        // Three-pane view of a merge: ours, base and theirs aligned chunk by chunk
+        function generateMergeView(merge) {
+            const CONTEXT = 3;
+            const panes = ['ours', 'base', 'theirs'];
+            const count = merge.conflicts.length;
+            const conflictChunks = new Map(merge.conflicts.map((conflict, i) => [conflict.chunk, { conflict, number: i + 1 }]));
+
+            let html = `<div class="file-diff">`;
+            html += `<div class="file-header">`;
+            html += `<span class="file-name">Merge of ${escapeHtml(merge.ours_path)} and ${escapeHtml(merge.theirs_path)}</span>`;
+            html += `<span class="file-badge">${count ? `${count} conflict(s)` : 'Merged cleanly'}</span>`;
+            html += `</div>`;
+            html += `<table class="diff-table merge-table">`;
+            html += `<tr class="merge-pane-header">`;
+            html += `<td colspan="2">Ours: ${escapeHtml(merge.ours_path)}</td>`;
+            html += `<td colspan="2">Base: ${escapeHtml(merge.base_path)}</td>`;
+            html += `<td colspan="2">Theirs: ${escapeHtml(merge.theirs_path)}</td>`;
+            html += `</tr>`;
+
+            merge.chunks.forEach((chunk, index) => {
+                const rows = Math.max(...panes.map(pane => chunk[pane].lines.length));
+                let shown = [...Array(rows).keys()];
+                let skipped = 0;
+
+                if (chunk.kind === 'unchanged') {
+                    // 较长的未改动区域只保留与改动相邻的几行
+                    const head = index === 0 ? 0 : CONTEXT;
+                    const tail = index === merge.chunks.length - 1 ? 0 : CONTEXT;
+                    if (rows > head + tail + 1) {
+                        skipped = rows - head - tail;
+                        shown = [...shown.slice(0, head), null, ...shown.slice(rows - tail)];
+                    }
+                }
+
+                const conflict = conflictChunks.get(index);
+                if (conflict) {
+                    html += `<tr class="merge-conflict-header" id="conflict-${conflict.number}">`;
+                    html += `<td colspan="6">Conflict ${conflict.number} of ${count} (line ${conflict.conflict.merged_line} of the merged file)</td>`;
+                    html += `</tr>`;
+                }
+
+                shown.forEach(row => {
+                    if (row === null) {
+                        html += `<tr class="merge-skipped"><td colspan="6">⋯ ${skipped} unchanged line(s)</td></tr>`;
+                        return;
+                    }
+                    html += `<tr>`;
+                    panes.forEach(pane => {
+                        const side = chunk[pane];
+                        const cls = mergePaneClass(chunk.kind, pane);
+                        if (row < side.lines.length) {
+                            html += `<td class="line-num ${cls}">${side.start + row}</td>`;
+                            html += `<td class="line-content ${cls}">${escapeHtml(side.lines[row])}</td>`;
+                        } else {
+                            html += `<td class="line-num"></td><td class="line-content"></td>`;
+                        }
+                    });
+                    html += `</tr>`;
+                });
+            });
+
+            html += `</table>`;
+            html += `</div>`;
+            return html;
+        }
+
+        // Color of a pane in a merge chunk: the side whose change was taken is
+        // added, the base it replaced is deleted
+        function mergePaneClass(kind, pane) {
+            if (kind === 'unchanged') return 'line-context';
+            if (kind === 'conflict') return 'merge-conflict';
+            if (pane === 'base') return 'line-delete';
+            const taken = kind === 'both' || kind === pane;
+            return taken ? 'line-add' : 'line-context';
+        }
+
+        // Render one side of a line, emphasizing intra-line changes of modified lines
+        function renderContent(line, side) {
+            if (line.inline) {
+                const segments = side === 'old' ? line.inline.old : line.inline.new;
+                const cls = side === 'old' ? 'inline-del' : 'inline-add';
+                return segments.map(seg => seg.emphasized
+                    ? `<span class="${cls}">${escapeHtml(seg.text)}</span>`
+                    : escapeHtml(seg.text)).join('');
+            }
+            const text = side === 'old' && line.old_content != null ? line.old_content : line.content;
+            return escapeHtml(text);
+        }
+
+        // Notes shown in the file header when the sides use different encodings or line endings
+        function fileBadges(file) {
+            let html = '';
+            if (file.status === 'renamed' || file.status === 'copied') {
+                const kind = file.status === 'renamed' ? 'Renamed' : 'Copied';
+                const similarity = file.similarity != null ? ` (${file.similarity}%)` : '';
+                html += `<span class="file-badge">${kind} from ${escapeHtml(file.old_path)}${similarity}</span>`;
+            }
+            const encodings = file.encodings;
+            if (encodings && encodings.old !== encodings.new) {
+                html += `<span class="file-badge">Encoding: ${escapeHtml(encodings.old)} → ${escapeHtml(encodings.new)}</span>`;
+            }
+            const lineEndings = file.line_endings;
+            if (lineEndings) {
+                html += `<span class="file-badge">Line endings: ${escapeHtml(lineEndings.old)} → ${escapeHtml(lineEndings.new)}</span>`;
+            }
+            const modes = file.modes;
+            if (modes && modes.old && modes.new && modes.old !== modes.new) {
+                html += `<span class="file-badge">Mode: ${escapeHtml(modes.old)} → ${escapeHtml(modes.new)}</span>`;
+            }
+            return html;
+        }
+
+        // "\ No newline at end of file" after the last line of a side without trailing newline
+        function eofMarker(file, line, side) {
+            const missing = file.missing_newline;
+            if (!missing) return '';
+            const isOldLast = missing.old != null && line.old_line_num === missing.old && line.change_type !== 'Add';
+            const isNewLast = missing.new != null && line.new_line_num === missing.new && line.change_type !== 'Delete';
+            const marked = side === 'old' ? isOldLast : side === 'new' ? isNewLast : isOldLast || isNewLast;
+            return marked ? `<span class="no-newline">\\ No newline at end of file</span>` : '';
+        }
+
+        // Hex dump of the differing regions of a binary file
+        function renderBinaryDiff(diff) {
+            const BYTES_PER_ROW = 16;
+            const hex = (n, width) => n.toString(16).padStart(width, '0');
+            const delta = diff.new_size - diff.old_size;
+
+            let html = `<div class="binary-summary">Size: ${diff.old_size} → ${diff.new_size} bytes (${delta >= 0 ? '+' : ''}${delta})`;
+            if (diff.first_difference != null) {
+                html += ` · first difference at offset 0x${hex(diff.first_difference, 8)} · ${diff.differing_bytes} byte(s) differ`;
+            }
+            html += `</div>`;
+
+            // One row of bytes, emphasizing the ones that differ from the other side
+            const hexRow = (bytes, other, cls) => {
+                let hexPart = '';
+                let asciiPart = '';
+                for (let i = 0; i < BYTES_PER_ROW; i++) {
+                    if (i === BYTES_PER_ROW / 2) hexPart += ' ';
+                    if (i >= bytes.length) {
+                        hexPart += '   ';
+                        continue;
+                    }
+                    const byte = bytes[i];
+                    const ch = byte >= 0x20 && byte < 0x7f ? String.fromCharCode(byte) : '.';
+                    const changed = other[i] !== byte;
+                    hexPart += (changed ? `<span class="${cls}">${hex(byte, 2)}</span>` : hex(byte, 2)) + ' ';
+                    asciiPart += changed ? `<span class="${cls}">${escapeHtml(ch)}</span>` : escapeHtml(ch);
+                }
+                return `${hexPart}|${asciiPart}|`;
+            };
+
+            html += `<table class="diff-table">`;
+            diff.regions.forEach(region => {
+                html += `<tr class="line-context"><td class="line-num hex-offset"></td><td class="line-content">@@ 0x${hex(region.offset, 8)} @@</td></tr>`;
+
+                const rows = Math.ceil(Math.max(region.old.length, region.new.length) / BYTES_PER_ROW);
+                for (let row = 0; row < rows; row++) {
+                    const start = row * BYTES_PER_ROW;
+                    const oldRow = region.old.slice(start, start + BYTES_PER_ROW);
+                    const newRow = region.new.slice(start, start + BYTES_PER_ROW);
+                    const offset = hex(region.offset + start, 8);
+                    if (oldRow.length > 0) {
+                        html += `<tr class="line-delete"><td class="line-num hex-offset">${offset}</td><td class="line-content">-${hexRow(oldRow, newRow, 'inline-del')}</td></tr>`;
+                    }
+                    if (newRow.length > 0) {
+                        html += `<tr class="line-add"><td class="line-num hex-offset">${offset}</td><td class="line-content">+${hexRow(newRow, oldRow, 'inline-add')}</td></tr>`;
+                    }
+                }
+            });
+            html += `</table>`;
+
+            if (diff.truncated) {
+                html += `<div class="binary-summary">Too many differences, output truncated</div>`;
+            }
+            return html;
+        }
+
+        // Unified view rows for a line; a modified line becomes a delete row and an add row
+        function unifiedRows(line, file) {
+            if (line.change_type === 'Modify') {
+                return [
+                    { changeClass: 'line-delete', oldLineNum: line.old_line_num, newLineNum: null, prefix: '-', html: renderContent(line, 'old') + eofMarker(file, line, 'old') },
+                    { changeClass: 'line-add', oldLineNum: null, newLineNum: line.new_line_num, prefix: '+', html: renderContent(line, 'new') + eofMarker(file, line, 'new') }
+                ];
+            }
+            const changeClass = line.change_type === 'Add' ? 'line-add' :
+                              line.change_type === 'Delete' ? 'line-delete' :
+                              'line-context';
+            const prefix = line.change_type === 'Add' ? '+' :
+                         line.change_type === 'Delete' ? '-' : ' ';
+            return [{ changeClass, oldLineNum: line.old_line_num, newLineNum: line.new_line_num, prefix, html: escapeHtml(line.content) + eofMarker(file, line, 'both') }];
+        }
+
         function escapeHtml(text) {
             const div = document.createElement('div');
             div.textContent = text;
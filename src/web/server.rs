@@ -143,7 +143,7 @@ async fn paginated_api_handler(
         .collect();
 
     let total_lines = all_lines.len();
-    let total_pages = (total_lines + page_size - 1) / page_size;
+    let total_pages = total_lines.div_ceil(page_size);
     let start = page * page_size;
     let end = (start + page_size).min(total_lines);
     let has_more = end < total_lines;
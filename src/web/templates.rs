@@ -1,12 +1,27 @@
+use anyhow::Result;
+use std::path::Path;
+
 use crate::diff::types::*;
 use super::assets::{get_css, get_js};
 
 pub struct HtmlTemplate;
 
 impl HtmlTemplate {
+    /// Write the self-contained page to a file, for viewing without the web server
+    ///
+    /// The diff data, CSS and JS are all inlined and the viewer never calls
+    /// the server API, so the report also works when opened from `file://`.
+    pub fn export<P: AsRef<Path>>(diff_result: &DiffResult, path: P) -> Result<()> {
+        std::fs::write(path, Self::generate(diff_result))?;
+        Ok(())
+    }
+
     /// Generate complete HTML page
     pub fn generate(diff_result: &DiffResult) -> String {
-        let diff_data_json = serde_json::to_string(diff_result).unwrap_or_else(|_| "{}".to_string());
+        // "</" 必须转义，否则文件内容中的 "</script>" 会提前结束脚本
+        let diff_data_json = serde_json::to_string(diff_result)
+            .unwrap_or_else(|_| "{}".to_string())
+            .replace("</", "<\\/");
 
         format!(
             r#"<!DOCTYPE html>
@@ -45,3 +60,55 @@ impl HtmlTemplate {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_tags_in_content_are_escaped() {
+        let diff_result = DiffResult {
+            files: vec![FileDiff {
+                path: "index.html".to_string(),
+                old_path: "index.html".to_string(),
+                new_path: "index.html".to_string(),
+                is_binary: false,
+                is_new: false,
+                is_deleted: false,
+                hunks: vec![Hunk {
+                    old_start: 1,
+                    old_count: 0,
+                    new_start: 1,
+                    new_count: 1,
+                    lines: vec![LineChange {
+                        change_type: ChangeType::Add,
+                        old_line_num: None,
+                        new_line_num: Some(1),
+                        content: "</script><script>alert(1)</script>".to_string(),
+                        old_content: None,
+                        inline: None,
+                    }],
+                }],
+                full_content: None,
+                binary_diff: None,
+                encodings: None,
+                line_endings: None,
+                missing_newline: None,
+                modes: None,
+                mtimes: None,
+                status: FileStatus::Modified,
+                similarity: None,
+                error: None,
+            }],
+            total_additions: 1,
+            total_deletions: 0,
+            total_files_changed: 1,
+            merge: None,
+        };
+
+        let html = HtmlTemplate::generate(&diff_result);
+
+        assert_eq!(html.matches("</script>").count(), 1);
+        assert!(html.contains(r"<\/script><script>alert(1)<\/script>"));
+    }
+}